@@ -121,14 +121,8 @@ impl ImageConverter {
     }
 
     pub fn set_width_and_height(&mut self, width: String, height: String) {
-        self.width = match width.parse::<u32>() {
-            Ok(width) => width,
-            Err(_) => 0,
-        };
-        self.height = match height.parse::<u32>() {
-            Ok(height) => height,
-            Err(_) => 0,
-        };
+        self.width = width.parse::<u32>().unwrap_or_default();
+        self.height = height.parse::<u32>().unwrap_or_default();
 
         if self.width == 0 || self.height == 0 {
             panic!("width or height is invalid");
@@ -186,34 +180,32 @@ impl ImageConverter {
                 let from_buf = BufReader::new(from_file);
                 let mut buf = Vec::new();
 
-                for line in from_buf.lines() {
-                    if let Ok(line) = line {
-                        let line = line.trim_end();
-                        if !line.is_empty() {
-                            let tmp = line
-                                .split(' ')
-                                .map(|x| u8::from_str_radix(x, 16).unwrap())
-                                .collect::<Vec<u8>>();
-                            let iter =
-                                tmp.chunks_exact(self.bin_file_format.bytes_per_sample as usize);
-
-                            for pixel in iter {
-                                let mut len = pixel.len();
-                                let mut value = 0 as u32;
-
-                                if self.bin_file_format.is_small_endian {
-                                    while len > 0 {
-                                        value = (value << 8) | pixel[len - 1] as u32;
-                                        len -= 1;
-                                    }
-                                } else {
-                                    while len < pixel.len() {
-                                        value = (value << 8) | pixel[len] as u32;
-                                        len += 1;
-                                    }
+                for line in from_buf.lines().map_while(Result::ok) {
+                    let line = line.trim_end();
+                    if !line.is_empty() {
+                        let tmp = line
+                            .split(' ')
+                            .map(|x| u8::from_str_radix(x, 16).unwrap())
+                            .collect::<Vec<u8>>();
+                        let iter =
+                            tmp.chunks_exact(self.bin_file_format.bytes_per_sample as usize);
+
+                        for pixel in iter {
+                            let mut len = pixel.len();
+                            let mut value = 0_u32;
+
+                            if self.bin_file_format.is_small_endian {
+                                while len > 0 {
+                                    value = (value << 8) | pixel[len - 1] as u32;
+                                    len -= 1;
+                                }
+                            } else {
+                                while len < pixel.len() {
+                                    value = (value << 8) | pixel[len] as u32;
+                                    len += 1;
                                 }
-                                buf.push(value);
                             }
+                            buf.push(value);
                         }
                     }
                 }
@@ -242,12 +234,11 @@ impl ImageConverter {
     fn image_from_vec(&self, width: u32, height: u32, buf: Vec<u32>) -> RgbImage {
         let mut image = RgbImage::new(width, height);
 
-        let pixel_fn: PixelToRgb;
-        match self.bin_file_format.rgb_type {
-            ColorFormat::Rgb332 => pixel_fn = rgb332_to_rgb,
-            ColorFormat::Rgb565 => pixel_fn = rgb565_to_rgb,
-            ColorFormat::Rgb888 => pixel_fn = rgb888_to_rgb,
-        }
+        let pixel_fn: PixelToRgb = match self.bin_file_format.rgb_type {
+            ColorFormat::Rgb332 => rgb332_to_rgb,
+            ColorFormat::Rgb565 => rgb565_to_rgb,
+            ColorFormat::Rgb888 => rgb888_to_rgb,
+        };
 
         for (i, pixel) in buf.iter().enumerate() {
             image.put_pixel(
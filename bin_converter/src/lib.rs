@@ -2,6 +2,7 @@ mod image_convertor;
 pub use image_convertor::*;
 
 mod music_convertor;
+#[allow(unused_imports)]
 pub use music_convertor::*;
 
 pub trait Convertor {
@@ -15,8 +16,8 @@ mod tests {
 
     #[test]
     fn test_convertor() {
-        let from = concat!(env!("CARGO_MANIFEST_DIR"), "\\examples\\img1.txt").to_string();
-        let to = concat!(env!("CARGO_MANIFEST_DIR"), "\\examples\\img1.png").to_string();
+        let from = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/img1.txt").to_string();
+        let to = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/img1.png").to_string();
         let width = String::from("320");
         let height = String::from("385");
         
@@ -1,7 +1,8 @@
 use bin_converter::*;
 use clap::Parser;
 use header_syncer::*;
-use std::env;
+use std::process;
+use std::time::Duration;
 
 /// EE_TOOLS
 #[derive(Parser)]
@@ -11,54 +12,244 @@ struct Args {
     action: Action,
 }
 
+/// Type of From files
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum FromType {
+    Header,
+    #[value(name = "gnu_lds")]
+    GnuLds,
+    Elf,
+    #[value(name = "gnu_map")]
+    GnuMap,
+}
+
+/// Parse mode of From headers
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Parse {
+    Text,
+    Ast,
+}
+
+/// What is written for the symbols of ELF From files
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ElfOutputType {
+    Extern,
+    Address,
+}
+
+/// What is written for GNU ld map From files
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum MapOutputType {
+    Defines,
+    Table,
+}
+
+/// What is done with the `#if` blocks of From headers
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Conditionals {
+    Evaluate,
+    Prune,
+}
+
 #[derive(clap::Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Action {
     /// A synchronous header file program
     HeaderSyncer {
         // /// Set the source file and output file
         // #[arg(short, default_value_t = String::new())]
         // set: String,
+        /// TOML job file listing the syncs to run, see `SyncJob`; e.g., `--config "sync.toml"`,
+        /// then the options of each job come from the file, only `--job`, `--extra-path-var`,
+        /// `--dry-run`, `--check` and `--watch` can be given with it
+        #[arg(long)]
+        config: Option<String>,
 
-        // /// This option is used to specify an initialization script file.
-        // #[arg(long, default_value_t = String::new())]
-        // init: String,
+        /// Jobs of the job file to run, all by default; e.g., `--job "gpio uart"`
+        #[arg(long, requires = "config", conflicts_with = "from")]
+        job: Option<String>,
 
         // /// Quiet
         // #[arg(short, default_value_t = false)]
         // quiet: bool,
         /// From files; e.g., `--from "api1.h api2.h"`
-        #[arg(long)]
-        from: String,
+        #[arg(long, required_unless_present = "config", conflicts_with = "config")]
+        from: Option<String>,
 
         /// To files; e.g., `--to "api.h test.h"`
-        #[arg(long)]
-        to: String,
+        #[arg(long, required_unless_present = "config", conflicts_with = "config")]
+        to: Option<String>,
 
-        /// Type of From files; e.g., `--type_of_from "gnu_lds"
-        #[arg(long, default_value_t = String::new())]
-        type_of_from: String,
+        /// Type of From files, `elf` also reads `.o` and `.a` files; e.g., `--type-of-from "gnu_lds"`
+        #[arg(long, value_enum, conflicts_with = "config", default_value_t = FromType::Header)]
+        type_of_from: FromType,
+
+        /// Parse mode of From headers; e.g., `--parse-mode "ast"`,
+        /// then each declaration is parsed by the C preprocessor and lang-c instead of copying text
+        #[arg(long, value_enum, conflicts_with = "config", default_value_t = Parse::Text)]
+        parse_mode: Parse,
 
         /// Sync label; e.g., `--sync-lable "/* header-sync */"`,
         /// then it will copy from '/* header-sync start */' to '/* header-sync end */'
-        #[arg(long, default_value_t = String::from("/* header-sync */"))]
+        #[arg(long, conflicts_with = "config", default_value_t = String::from("/* header-sync */"))]
         sync_lable: String,
 
-        /// TODO: Class name; e.g., `--class-name "test"`,
+        /// Class name; e.g., `--class-name "test"`,
         /// then it will add `// test` to the start of the sync code
-        #[arg(long, default_value_t = String::new())]
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
         class_name: String,
 
-        /// TODO: Ignore symbol; e.g., `--ignore-symbol "sym1 sym2"`
-        #[arg(long, default_value_t = String::new())]
+        /// Ignore symbol, as exact names, globs or `/regex/`; e.g., `--ignore-symbol "sym1 test_* /^tmp_/"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
         ignore_symbol: String,
 
-        /// TODO: Symbol compression mode; e.g., `--comp
-        #[arg(short, long, default_value_t = true)]
+        /// Only sync the symbols matching these patterns; e.g., `--keep-symbol "rom_* /^ROM_/"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        keep_symbol: String,
+
+        /// Mark symbols as `pattern=mark`, the mark is `weak`, `deprecated`, `section:<name>` or written as it is;
+        /// e.g., `--mark-symbol "rom_*=ROM_API old_init=deprecated"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        mark_symbol: String,
+
+        /// Write the prototypes of a region as a jump table at a fixed address, as `[region=]name@address`;
+        /// `.c` To files get the table, `.ld` files its output section and others the struct;
+        /// e.g., `--jump-table "rom=rom_api@0x00010000"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        jump_table: String,
+
+        /// Write `PROVIDE(symbol = address);` lines into the `.ld` To files, with the addresses from
+        /// an ELF, map or CSV file; e.g., `--provide-addresses "rom.elf"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        provide_addresses: String,
+
+        /// Write each declaration on one line without comments or blank lines,
+        /// and drop repeated declarations
+        #[arg(short, long, conflicts_with = "config", default_value_t = false)]
         compress: bool,
 
-        /// Add additional path variables; e.g., `--extra_path_var path_to\gcc`
+        /// Tidy the autogen blocks after syncing: group the declarations by kind, sort them,
+        /// drop repeated ones and normalise white space; see also the `tidy` command
+        #[arg(long, conflicts_with = "config", default_value_t = false)]
+        tidy: bool,
+
+        /// What to write for the symbols of ELF From files, `extern` declarations or `address` constants
+        #[arg(long, value_enum, conflicts_with = "config", default_value_t = ElfOutputType::Extern)]
+        elf_output: ElfOutputType,
+
+        /// What to write for GNU ld map From files, `defines` of the regions, sections and symbols
+        /// or a symbol `table`
+        #[arg(long, value_enum, conflicts_with = "config", default_value_t = MapOutputType::Defines)]
+        map_output: MapOutputType,
+
+        /// Also sync the declarations of the types the synced symbols use, from any From file
+        #[arg(long, conflicts_with = "config", default_value_t = false)]
+        resolve_types: bool,
+
+        /// Define macros for the `#if` blocks of the From headers; e.g., `--define "CONFIG_UART CONFIG_UART_NUM=2"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        define: String,
+
+        /// Undefine macros for the `#if` blocks of the From headers; e.g., `--undefine "CONFIG_DEBUG"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        undefine: String,
+
+        /// Directories to search the headers included by the From headers in; e.g., `--include-path "inc drivers"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        include_path: String,
+
+        /// What to do with the `#if` blocks of the From headers, `evaluate` keeps only the active branches,
+        /// `prune` also keeps the `#if` lines; evaluated by default if any macro or include path is given
+        #[arg(long, value_enum, conflicts_with = "config")]
+        conditionals: Option<Conditionals>,
+
+        /// Encoding of the From and To files that are not UTF-8; e.g., `--encoding "gbk"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        encoding: String,
+
+        /// C preprocessor of the `ast` parse mode, `gcc -E` by default, it is given the `--define`,
+        /// `--undefine` and `--include-path` options; e.g., `--cpp "arm-none-eabi-gcc -E"`
+        #[arg(long, conflicts_with = "config", default_value_t = String::new())]
+        cpp: String,
+
+        /// Directories to search the C preprocessor in before PATH, separated like PATH
+        /// (`:` on Unix, `;` on Windows), also for the jobs; e.g., `--extra-path-var /opt/gcc-arm/bin`
         #[arg(long, default_value_t = String::new())]
         extra_path_var: String,
+
+        /// Print the changes as a unified diff instead of writing the To files
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+
+        /// Merge the symbols into the autogen block, keeping the order and formatting of the To files
+        #[arg(long, conflicts_with = "config", default_value_t = false)]
+        incremental: bool,
+
+        /// Write a provenance line (tool version, From files, input hash, time) into each autogen block
+        #[arg(long, conflicts_with = "config", default_value_t = false)]
+        stamp: bool,
+
+        /// Only check that the To files are up to date, list the stale ones and fail if there are any
+        #[arg(long, default_value_t = false)]
+        check: bool,
+
+        /// Keep running and sync again whenever a From file changes
+        #[arg(long, default_value_t = false)]
+        watch: bool,
+
+        /// Number of replaced autogen blocks kept in `.header_syncer/` next to each To file, none by default
+        #[arg(long, conflicts_with = "config", default_value_t = 0)]
+        history: usize,
+    },
+
+    /// Restore an autogen block saved by header-syncer
+    Restore {
+        /// To file to restore; e.g., `--to "api.h"`
+        #[arg(long)]
+        to: String,
+
+        /// Generation to restore, the newest one by default; e.g., `--generation "20230101-120000"`
+        #[arg(long, default_value_t = String::new())]
+        generation: String,
+
+        /// Sync label used by header-syncer
+        #[arg(long, default_value_t = String::from("/* header-sync */"))]
+        sync_lable: String,
+
+        /// Name of the autogen block to restore, the unnamed one by default; e.g., `--region "gpio"`
+        #[arg(long, default_value_t = String::new())]
+        region: String,
+
+        /// Encoding of the To file if it is not UTF-8; e.g., `--encoding "gbk"`
+        #[arg(long, default_value_t = String::new())]
+        encoding: String,
+
+        /// List the saved generations instead of restoring
+        #[arg(long, default_value_t = false)]
+        list: bool,
+    },
+
+    /// Tidy the autogen blocks of To files as they are, without syncing
+    Tidy {
+        /// To files to tidy; e.g., `--to "api.h test.h"`
+        #[arg(long)]
+        to: String,
+
+        /// Sync label used by header-syncer
+        #[arg(long, default_value_t = String::from("/* header-sync */"))]
+        sync_lable: String,
+
+        /// Class name used by header-syncer, its `// <class>` line is kept first
+        #[arg(long, default_value_t = String::new())]
+        class_name: String,
+
+        /// Encoding of To files that are not UTF-8; e.g., `--encoding "gbk"`
+        #[arg(long, default_value_t = String::new())]
+        encoding: String,
+
+        /// Number of replaced autogen blocks kept in `.header_syncer/` next to each To file, none by default
+        #[arg(long, default_value_t = 0)]
+        history: usize,
     },
 
     /// File format convertor
@@ -98,37 +289,215 @@ fn main() {
     match args.action {
         Action::HeaderSyncer {
             // set,
-            // init,
+            config,
+            job,
             // quiet,
             from,
             to,
             type_of_from,
+            parse_mode,
             sync_lable,
             class_name,
             ignore_symbol,
+            keep_symbol,
+            mark_symbol,
+            jump_table,
+            provide_addresses,
             compress,
+            tidy,
+            elf_output,
+            map_output,
+            resolve_types,
+            define,
+            undefine,
+            include_path,
+            conditionals,
+            encoding,
+            cpp,
             extra_path_var,
+            dry_run,
+            incremental,
+            stamp,
+            check,
+            watch,
+            history,
+        } => {
+            let mut syncers = if let Some(config) = config {
+                match load_jobs(&config, &job.unwrap_or_default(), &extra_path_var) {
+                    Ok(syncers) => syncers,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+            } else {
+                let from = from.unwrap_or_default();
+                let to = to.unwrap_or_default();
+                let isyms = ignore_symbol.split(' ').collect();
+                let ksyms = keep_symbol.split(' ').collect();
+                let msyms = mark_symbol.split(' ').collect();
+                let mut syncer = Syncer::new(
+                    from.split(' ').collect(),
+                    to.split(' ').collect(),
+                    &sync_lable,
+                );
+
+                syncer.set_type_of_form(match type_of_from {
+                    FromType::Header => FromFileType::Header,
+                    FromType::GnuLds => FromFileType::GnuLinkScript,
+                    FromType::Elf => FromFileType::Elf,
+                    FromType::GnuMap => FromFileType::GnuMap,
+                });
+                syncer.set_elf_output(match elf_output {
+                    ElfOutputType::Extern => ElfOutput::Extern,
+                    ElfOutputType::Address => ElfOutput::Address,
+                });
+                syncer.set_map_output(match map_output {
+                    MapOutputType::Defines => MapOutput::Defines,
+                    MapOutputType::Table => MapOutput::Table,
+                });
+                syncer.set_parse_mode(match parse_mode {
+                    Parse::Text => ParseMode::Text,
+                    Parse::Ast => ParseMode::Ast,
+                });
+
+                syncer.set_class_name(&class_name);
+                syncer.set_ignore_symbols(isyms);
+                syncer.set_keep_symbols(ksyms);
+                syncer.set_mark_symbols(msyms);
+                syncer.set_jump_tables(jump_table.split(' ').collect());
+                if !provide_addresses.is_empty() {
+                    syncer.set_provide_addresses(&provide_addresses);
+                }
+                syncer.set_compress(compress);
+                syncer.set_tidy(tidy);
+                syncer.set_resolve_types(resolve_types);
+                syncer.set_history(history);
+                syncer.set_incremental(incremental);
+                syncer.set_stamp(stamp);
+                if !(define.is_empty()
+                    && undefine.is_empty()
+                    && include_path.is_empty()
+                    && conditionals.is_none())
+                {
+                    let mode = match conditionals {
+                        None | Some(Conditionals::Evaluate) => ConditionalMode::Evaluate,
+                        Some(Conditionals::Prune) => ConditionalMode::Prune,
+                    };
+                    let mut pp = Preprocessor::new(mode);
+                    define.split_whitespace().for_each(|d| pp.define(d));
+                    undefine.split_whitespace().for_each(|u| pp.undefine(u));
+                    include_path
+                        .split_whitespace()
+                        .for_each(|i| pp.include_path(i));
+                    syncer.set_preprocessor(pp);
+                }
+                if !(cpp.is_empty() && extra_path_var.is_empty()) {
+                    let mut toolchain = Toolchain::new(&cpp);
+                    toolchain.add_path(&extra_path_var);
+                    syncer.set_toolchain(toolchain);
+                }
+                if !encoding.is_empty() {
+                    if let Err(e) = syncer.set_encoding(&encoding) {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+                vec![syncer]
+            };
+
+            if check {
+                match check_targets(&mut syncers) {
+                    Ok(true) => println!("all To files are up to date"),
+                    Ok(false) => process::exit(1),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+                return;
+            }
+            for syncer in &mut syncers {
+                if let Err(e) = sync(syncer, dry_run) {
+                    eprintln!("{}", e);
+                    // a watch syncs again once the From files are fixed
+                    if !watch {
+                        process::exit(1);
+                    }
+                }
+            }
+            if watch {
+                watch_sources(&mut syncers, dry_run);
+            }
+        }
+        Action::Restore {
+            to,
+            generation,
+            sync_lable,
+            region,
+            encoding,
+            list,
         } => {
-            let from = from.split(' ').collect();
-            let to = to.split(' ').collect();
-            let isyms = ignore_symbol.split(' ').collect();
-            let mut syncer = Syncer::new(from, to, &sync_lable);
-
-            // Add additional path variables
-            let mut sys_path = env::var_os("path").unwrap();
-            sys_path.push(";");
-            sys_path.push(extra_path_var);
-            env::set_var("path", sys_path);
-
-            if type_of_from == "gnu_lds" {
-                syncer.set_type_of_form(FromFileType::GnuLinkScript);
+            if list {
+                match History::new(&to, &region) {
+                    Ok(history) => history.generations().iter().for_each(|g| println!("{}", g)),
+                    Err(e) => {
+                        eprintln!("{}: {}", to, e);
+                        process::exit(1);
+                    }
+                }
+                return;
             }
 
+            let mut syncer = Syncer::new(Vec::new(), vec![&to], &sync_lable);
+            if !encoding.is_empty() {
+                if let Err(e) = syncer.set_encoding(&encoding) {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            let generation = if generation.is_empty() {
+                None
+            } else {
+                Some(generation.as_str())
+            };
+            match syncer.restore(&to, &region, generation) {
+                Ok(generation) => println!("{} restored to {}", to, generation),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Action::Tidy {
+            to,
+            sync_lable,
+            class_name,
+            encoding,
+            history,
+        } => {
+            let mut syncer = Syncer::new(Vec::new(), to.split(' ').collect(), &sync_lable);
             syncer.set_class_name(&class_name);
-            syncer.set_ignore_symbols(isyms);
-            // syncer.set_mark_symbols(mark)
-            syncer.set_compress(compress);
-            syncer.run();
+            syncer.set_history(history);
+            if !encoding.is_empty() {
+                if let Err(e) = syncer.set_encoding(&encoding) {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+
+            match syncer.tidy() {
+                Ok(written) => {
+                    for change in &written {
+                        println!("{} tidied", change.path);
+                    }
+                    print_warnings(&syncer);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
         }
         Action::Converter {
             init: _,
@@ -148,3 +517,117 @@ fn main() {
         }
     }
 }
+
+/// Runs a syncer, or prints the changes it would make if `dry_run`
+fn sync(syncer: &mut Syncer, dry_run: bool) -> Result<(), SyncError> {
+    if dry_run {
+        for change in syncer.plan()? {
+            print!("{}", change.unified_diff());
+        }
+    } else {
+        syncer.run()?;
+    }
+    print_warnings(syncer);
+    Ok(())
+}
+
+/// Prints the warnings of the last sync to stderr, apart from the diff and check output
+fn print_warnings(syncer: &Syncer) {
+    for warning in syncer.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+}
+
+/// Prints the To files and autogen blocks a sync would change, returns whether there are none
+fn check_targets(syncers: &mut [Syncer]) -> Result<bool, SyncError> {
+    let mut up_to_date = true;
+
+    for syncer in syncers {
+        let changes = syncer.plan()?;
+        print_warnings(syncer);
+        for change in changes {
+            if !change.is_changed() {
+                continue;
+            }
+
+            up_to_date = false;
+            let regions: Vec<String> = change
+                .regions
+                .iter()
+                .map(|r| match r.as_str() {
+                    "" => "autogen".to_string(),
+                    r => format!("autogen:{}", r),
+                })
+                .collect();
+            if regions.is_empty() {
+                println!("{} is out of date outside its autogen blocks", change.path);
+            } else {
+                println!("{} is out of date: {}", change.path, regions.join(" "));
+            }
+        }
+    }
+
+    Ok(up_to_date)
+}
+
+/// Syncs again the syncers whose From files change, until the process is stopped
+fn watch_sources(syncers: &mut [Syncer], dry_run: bool) -> ! {
+    let sources: Vec<String> = syncers.iter().flat_map(|s| s.sources()).collect();
+    let mut watcher = FileWatcher::new(&sources);
+    println!("watching {} files", sources.len());
+
+    loop {
+        let changed = watcher.wait(Duration::from_millis(200), Duration::from_millis(500));
+        println!("changed: {}", changed.join(" "));
+
+        for syncer in syncers
+            .iter_mut()
+            .filter(|s| s.sources().iter().any(|f| changed.contains(f)))
+        {
+            let res = if dry_run {
+                sync(syncer, true)
+            } else {
+                syncer.run().map(|written| {
+                    for change in &written {
+                        let (added, removed) = change.line_counts();
+                        println!("{} updated, +{} -{}", change.path, added, removed);
+                    }
+                    if written.is_empty() {
+                        println!("no To file changed");
+                    }
+                    print_warnings(syncer);
+                })
+            };
+            if let Err(e) = res {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+/// Returns the syncers of the jobs in a job file, `names` picks the jobs, all if it is empty.
+/// `tool_path` is searched for the preprocessor before the tool path of each job.
+fn load_jobs(config: &str, names: &str, tool_path: &str) -> Result<Vec<Syncer>, SyncError> {
+    let mut jobs = SyncJob::load(config)?;
+    if !tool_path.is_empty() {
+        for job in &mut jobs {
+            job.tool_path.insert(0, tool_path.to_string());
+        }
+    }
+    let names: Vec<&str> = names.split(' ').filter(|s| !s.is_empty()).collect();
+    if let Some(name) = names
+        .iter()
+        .find(|name| !jobs.iter().any(|j| j.name == **name))
+    {
+        return Err(SyncError::Job {
+            path: config.to_string(),
+            message: format!("no job named {}", name),
+        });
+    }
+
+    Ok(jobs
+        .iter()
+        .filter(|job| names.is_empty() || names.contains(&job.name.as_str()))
+        .map(|job| job.syncer())
+        .collect())
+}
@@ -0,0 +1,138 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn header_syncer(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ee_tools_core"))
+        .arg("header-syncer")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Returns an empty directory of its own for a test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ee_tools_core_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_check() {
+    let dir = temp_dir("check");
+    let from = dir.join("a.h");
+    let to = dir.join("api.h");
+    fs::write(
+        &from,
+        "/* header-sync start */\nvoid a(void);\n/* header-sync end */\n",
+    )
+    .unwrap();
+    fs::write(
+        &to,
+        "/* header-sync autogen start */\n/* header-sync autogen end */\n",
+    )
+    .unwrap();
+    let args = [
+        "--from",
+        from.to_str().unwrap(),
+        "--to",
+        to.to_str().unwrap(),
+    ];
+
+    let res = header_syncer(&[&args[..], &["--check"]].concat());
+    assert_eq!(res.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&res.stdout),
+        format!("{} is out of date: autogen\n", to.display())
+    );
+
+    assert!(header_syncer(&args).status.success());
+    let res = header_syncer(&[&args[..], &["--check"]].concat());
+    assert_eq!(res.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&res.stdout),
+        "all To files are up to date\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unknown_values() {
+    for option in [
+        "--type-of-from",
+        "--parse-mode",
+        "--elf-output",
+        "--map-output",
+        "--conditionals",
+    ] {
+        let res = header_syncer(&["--from", "a.h", "--to", "b.h", option, "none"]);
+        assert_eq!(res.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&res.stderr).contains("invalid value 'none'"));
+    }
+}
+
+#[test]
+fn test_config_options() {
+    let res = header_syncer(&["--config", "sync.toml", "--class-name", "rom"]);
+    assert!(!res.status.success());
+    assert!(String::from_utf8_lossy(&res.stderr)
+        .contains("'--config <CONFIG>' cannot be used with '--class-name <CLASS_NAME>'"));
+
+    let res = header_syncer(&["--from", "a.h", "--to", "b.h", "--job", "rom"]);
+    assert!(!res.status.success());
+    assert!(String::from_utf8_lossy(&res.stderr).contains("cannot be used with '--job <JOB>'"));
+}
+
+#[test]
+fn test_watch_after_error() {
+    let dir = temp_dir("watch");
+    let from = dir.join("a.h");
+    let to = dir.join("api.h");
+    let _ = fs::remove_file(&from);
+    fs::write(
+        &to,
+        "/* header-sync autogen start */\n/* header-sync autogen end */\n",
+    )
+    .unwrap();
+
+    // the From file is missing, the first sync fails and the watch goes on
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ee_tools_core"))
+        .arg("header-syncer")
+        .args([
+            "--from",
+            from.to_str().unwrap(),
+            "--to",
+            to.to_str().unwrap(),
+        ])
+        .arg("--watch")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // kept open, the watch prints what it syncs
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "watching 1 files\n");
+
+    fs::write(
+        &from,
+        "/* header-sync start */\nvoid a(void);\n/* header-sync end */\n",
+    )
+    .unwrap();
+    let start = Instant::now();
+    let mut synced = false;
+    while !synced && start.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+        synced = fs::read_to_string(&to).unwrap().contains("void a(void);");
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(synced);
+}
@@ -23,6 +23,12 @@ void func1(void);
 extern void func2(void);
 /* header-sync autogen end */
 
+/* header-sync autogen:rom start */
+// rom.ld: header-sync:rom
+#define ROM_PRINTF_ADDR 0x00001234
+extern char rom_printf[];
+/* header-sync autogen:rom end */
+
 // ----------------------------------------------------------------
 //ss
 #endif
@@ -0,0 +1,24 @@
+// Source of rom.elf, librom.a and rom.map, the ELF and map file examples of header-syncer,
+// built with
+//   gcc -g -fno-pic -c rom.c -o rom.o && ar rcs librom.a rom.o
+//   gcc -g -nostdlib -static -no-pie -Wl,-Ttext=0x1000 -Wl,--build-id=none \
+//       -Wl,-z,max-page-size=16 -Wl,-z,noseparate-code rom.o -o rom.elf
+//   gcc -nostdlib -static -no-pie -Wl,-T,map.ld -Wl,--build-id=none -Wl,-Map=rom.map \
+//       rom.o -o rom_map.elf
+
+typedef unsigned int u32;
+struct rom_cfg {
+    u32 flags;
+};
+
+int rom_version = 3;
+const char *const rom_name = "rom";
+struct rom_cfg rom_cfgs[4];
+static int rom_private;
+
+int rom_add(int a, int b) { return a + b + rom_private; }
+void rom_init(struct rom_cfg *cfg, void (*cb)(u32)) { cb(cfg->flags); }
+int rom_printf(const char *fmt, ...) { return fmt[0]; }
+void rom_reset(void) {}
+__attribute__((visibility("hidden"))) void rom_hidden(void) {}
+void _start(void) {}
@@ -0,0 +1,41 @@
+#ifndef __TEST2_H__
+#define __TEST2_H__
+
+#include <stdint.h>
+
+/* header-sync start */
+#define TEST2_MAX 16
+#define TEST2_SUM(a, b) \
+    ((a) + (b))
+
+typedef uint8_t test2_id_t;
+
+/** Configuration of test2 */
+typedef struct
+{
+    test2_id_t id;
+    uint32_t flags;
+} test2_cfg_t;
+
+union test2_value
+{
+    int i;
+    float f;
+};
+
+enum test2_state {
+    TEST2_IDLE,
+    TEST2_BUSY,
+};
+
+extern int test2_count;
+typedef void (*test2_cb_t)(int evt);
+
+// Initialise the module
+int test2_init(const test2_cfg_t *cfg,
+               test2_cb_t cb);
+/* header-sync end */
+
+int test2_private(void);
+
+#endif
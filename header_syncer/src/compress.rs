@@ -0,0 +1,114 @@
+/// Returns the canonical one-line form of a declaration or directive, e.g.
+/// `int f(int a, int b);` for `int f( int a,\n      /* b */ int b );`
+///
+/// Comments are removed, macro continuations are joined and white space is collapsed,
+/// except inside string and character literals. A text holding only comments becomes empty.
+pub fn compress_declaration(text: &str) -> String {
+    let code = remove_comments(text).replace("\\\n", " ");
+    let mut out = String::new();
+    let mut space = false;
+    let mut chars = code.chars();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space
+            && !out.is_empty()
+            && !out.ends_with(['(', '['])
+            && !matches!(c, ')' | ']' | ',' | ';')
+        {
+            out.push(' ');
+        }
+        space = false;
+
+        out.push(c);
+        if c == '"' || c == '\'' {
+            while let Some(s) = chars.next() {
+                out.push(s);
+                if s == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        out.push(escaped);
+                    }
+                } else if s == c {
+                    break;
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Returns `text` with each comment replaced by a space, line breaks are kept
+fn remove_comments(text: &str) -> String {
+    let mut code = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut prev = ' ';
+            for s in chars.by_ref() {
+                if prev == '*' && s == '/' {
+                    break;
+                }
+                prev = s;
+            }
+            code.push(' ');
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            while chars.next_if(|s| *s != '\n').is_some() {}
+        } else if c == '"' || c == '\'' {
+            code.push(c);
+            while let Some(s) = chars.next() {
+                code.push(s);
+                if s == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        code.push(escaped);
+                    }
+                } else if s == c {
+                    break;
+                }
+            }
+        } else {
+            code.push(c);
+        }
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress_declaration;
+    use crate::test_util::{autogen_block, SyncTest};
+
+    #[test]
+    fn test_compress() {
+        assert_eq!(
+            compress_declaration("int f( int a, // first\n      /* b */ char *b );"),
+            "int f(int a, char *b);"
+        );
+        assert_eq!(
+            compress_declaration("#define MSG  \"a  // b\" \\\n    /* c */ \"d\""),
+            "#define MSG \"a  // b\" \"d\""
+        );
+        assert_eq!(compress_declaration("/* only\n a comment */"), "");
+
+        let test = SyncTest::new(
+            "compress",
+            "/** doc */\nstruct dev;\n\ntypedef struct {\n    int a;\n} cfg_t;\nstruct dev;\n",
+        );
+        let mut syncer = test.syncer();
+        syncer.set_compress(true);
+        syncer.run().unwrap();
+        assert_eq!(
+            test.target(),
+            autogen_block(
+                "",
+                "// test1.h: header-sync\nstruct dev;\ntypedef struct { int a; } cfg_t;\n"
+            )
+        );
+    }
+}
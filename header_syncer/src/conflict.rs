@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use crate::deps::{code_tokens, is_ident, BASE_TYPES, KEYWORDS};
+use crate::{defined_types, Symbol, SymbolKind, SyncError};
+
+/// Finds the symbols synced into a region from more than one From file.
+///
+/// A symbol declared the same way again is a duplicate to fold, a symbol declared
+/// differently, e.g. with another prototype or struct body, is a conflict. So is an
+/// enum constant of two different enums. Storage classes and parameter names are
+/// not compared.
+#[derive(Debug, Default)]
+pub struct ConflictChecker {
+    /// File, location and tokens of each symbol, by region, kind and name
+    symbols: HashMap<(String, SymbolKind, String), (String, String, String)>,
+    /// Location of each enum constant, by region and name
+    constants: HashMap<(String, String), (String, String)>,
+}
+
+impl ConflictChecker {
+    pub fn new() -> ConflictChecker {
+        ConflictChecker::default()
+    }
+
+    /// Returns whether the symbol of `path` repeats one of another From file,
+    /// or an error if it conflicts with one
+    pub fn check(&mut self, region: &str, path: &str, sym: &Symbol) -> Result<bool, SyncError> {
+        if sym.name.is_empty() || sym.kind == SymbolKind::Other {
+            return Ok(false);
+        }
+        // forward declarations don't conflict with the definition
+        if matches!(
+            sym.kind,
+            SymbolKind::Struct | SymbolKind::Union | SymbolKind::Enum
+        ) && defined_types(sym).is_empty()
+        {
+            return Ok(false);
+        }
+
+        let location = format!("{}:{}", path, sym.line);
+        let text = signature(sym);
+        let key = (region.to_string(), sym.kind, sym.name.clone());
+        if let Some((first_path, first, first_text)) = self.symbols.get(&key) {
+            if first_path == path {
+                return Ok(false);
+            }
+            if *first_text == text {
+                return Ok(true);
+            }
+            return Err(SyncError::Conflict {
+                symbol: sym.name.clone(),
+                first: first.clone(),
+                second: location,
+            });
+        }
+        self.symbols
+            .insert(key, (path.to_string(), location.clone(), text));
+
+        for constant in enum_constants(sym) {
+            let key = (region.to_string(), constant.clone());
+            if let Some((first_path, first)) = self.constants.get(&key) {
+                if first_path != path {
+                    return Err(SyncError::Conflict {
+                        symbol: format!("enum constant {}", constant),
+                        first: first.clone(),
+                        second: location,
+                    });
+                }
+            }
+            self.constants
+                .insert(key, (path.to_string(), location.clone()));
+        }
+
+        Ok(false)
+    }
+}
+
+/// Storage classes and function specifiers, they don't make two declarations incompatible
+const STORAGE_CLASSES: [&str; 6] = [
+    "extern",
+    "static",
+    "inline",
+    "__inline",
+    "__inline__",
+    "register",
+];
+
+/// Returns the tokens of a declaration as they are compared, without storage classes
+/// and without the parameter names of a function, so `extern void f(int a);`
+/// and `void f(int);` are the same
+fn signature(sym: &Symbol) -> String {
+    let mut tokens: Vec<String> = code_tokens(&sym.text)
+        .into_iter()
+        .filter(|t| !STORAGE_CLASSES.contains(&t.as_str()))
+        .collect();
+    if sym.kind != SymbolKind::Function {
+        return tokens.join(" ");
+    }
+
+    let open = match tokens
+        .windows(2)
+        .position(|w| w[0] == sym.name && w[1] == "(")
+    {
+        Some(p) => p + 1,
+        None => return tokens.join(" "),
+    };
+    let mut names = Vec::new();
+    let mut depth = 0;
+    let mut start = open + 1;
+    for i in open..tokens.len() {
+        match tokens[i].as_str() {
+            "(" | "[" => depth += 1,
+            ")" | "]" => {
+                depth -= 1;
+                if depth == 0 {
+                    names.extend(param_name(&tokens[start..i]).map(|n| start + n));
+                    break;
+                }
+            }
+            "," if depth == 1 => {
+                names.extend(param_name(&tokens[start..i]).map(|n| start + n));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    for i in names.into_iter().rev() {
+        tokens.remove(i);
+    }
+
+    tokens.join(" ")
+}
+
+/// Returns the position of the name in the tokens of a parameter, `None` if it has none
+fn param_name(tokens: &[String]) -> Option<usize> {
+    // a function pointer, `void (*cb)(int)`
+    if let Some(p) = tokens
+        .windows(4)
+        .position(|w| w[0] == "(" && w[1] == "*" && is_ident(&w[2]) && w[3] == ")")
+    {
+        return Some(p + 2);
+    }
+
+    let end = tokens.iter().position(|t| t == "[").unwrap_or(tokens.len());
+    let name = end.checked_sub(1)?;
+    let is_word = |t: &String| BASE_TYPES.contains(&t.as_str()) || KEYWORDS.contains(&t.as_str());
+    // the name follows a type, e.g. `int`, `cfg_t` or `struct cfg`, and not only qualifiers
+    let has_type = tokens[..name].iter().enumerate().any(|(i, t)| {
+        BASE_TYPES.contains(&t.as_str())
+            || (is_ident(t) && !is_word(t))
+            || (i > 0 && matches!(tokens[i - 1].as_str(), "struct" | "union" | "enum"))
+    });
+    (is_ident(&tokens[name]) && !is_word(&tokens[name]) && has_type).then_some(name)
+}
+
+/// Returns the constants of an enum definition
+fn enum_constants(sym: &Symbol) -> Vec<String> {
+    let mut constants = Vec::new();
+    if !matches!(sym.kind, SymbolKind::Enum | SymbolKind::Typedef) {
+        return constants;
+    }
+
+    let tokens = code_tokens(&sym.text);
+    let start = match tokens
+        .iter()
+        .position(|t| t == "enum")
+        .and_then(|p| tokens[p..].iter().position(|t| t == "{").map(|b| p + b))
+    {
+        Some(start) => start,
+        None => return constants,
+    };
+
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate().skip(start) {
+        match t.as_str() {
+            "{" | "(" => depth += 1,
+            "}" | ")" => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ if depth == 1 && matches!(tokens[i - 1].as_str(), "{" | ",") => {
+                constants.push(t.clone())
+            }
+            _ => {}
+        }
+    }
+
+    constants
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{sync_region, SyncTest, EMPTY_BLOCK, LABEL};
+    use crate::{SyncError, Syncer};
+
+    #[test]
+    fn test_conflicts() {
+        let test = SyncTest::new(
+            "conflicts",
+            "struct cfg {\n    int a;\n};\nvoid init(struct cfg *c);\n\
+             enum mode { MODE_A, MODE_B = 2 };\n",
+        );
+        let from2 = test.dir.write(
+            "test2.h",
+            sync_region(
+                "struct cfg;\n/** init */\nextern void  init(struct cfg *);\nvoid deinit(void);\n",
+            ),
+        );
+        let from2 = from2.to_str().unwrap();
+        let syncer = || Syncer::new(vec![&test.from, from2], vec![&test.to], LABEL);
+
+        syncer().run().unwrap();
+        let res = test.target();
+        assert_eq!(res.matches("init(").count(), 2);
+        assert!(res.contains("// test2.h: header-sync\nstruct cfg;\nvoid deinit(void);\n"));
+
+        let conflict = |code: &str, symbol: &str, line: usize| {
+            fs::write(from2, sync_region(code)).unwrap();
+            fs::write(&test.to, EMPTY_BLOCK).unwrap();
+            match syncer().run() {
+                Err(SyncError::Conflict {
+                    symbol: s,
+                    first,
+                    second,
+                }) => {
+                    assert_eq!(s, symbol);
+                    assert!(first.ends_with(&format!("test1.h:{}", line)));
+                    assert!(second.ends_with("test2.h:2"));
+                }
+                res => panic!("{:?}", res.map(|_| ())),
+            }
+            assert_eq!(test.target(), EMPTY_BLOCK);
+        };
+        conflict("void init(struct cfg *c, int n);\n", "init", 5);
+        conflict("void init(const struct cfg *c);\n", "init", 5);
+        conflict("struct cfg {\n    long a;\n};\n", "cfg", 2);
+        conflict("enum other { MODE_B };\n", "enum constant MODE_B", 6);
+    }
+}
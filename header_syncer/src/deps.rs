@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::symbol::{strip_comments, tokenize_code};
+use crate::{Symbol, SymbolKind};
+
+/// A type a declaration defines or uses
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TypeName {
+    /// A `struct`, `union` or `enum` tag, e.g. `struct test1`
+    Tag(&'static str, String),
+    /// A typedef name, e.g. `test1_t`
+    Typedef(String),
+}
+
+impl fmt::Display for TypeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeName::Tag(keyword, name) => write!(f, "{} {}", keyword, name),
+            TypeName::Typedef(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Type specifiers, never after a typedef name
+pub(crate) const BASE_TYPES: [&str; 11] = [
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "_Bool",
+    "_Complex",
+];
+
+/// Other words that are not typedef names
+pub(crate) const KEYWORDS: [&str; 23] = [
+    "const",
+    "volatile",
+    "restrict",
+    "__restrict",
+    "static",
+    "extern",
+    "inline",
+    "__inline",
+    "_Noreturn",
+    "register",
+    "auto",
+    "typedef",
+    "struct",
+    "union",
+    "enum",
+    "sizeof",
+    "_Alignas",
+    "_Atomic",
+    "__extension__",
+    "return",
+    "if",
+    "else",
+    "while",
+];
+
+/// Types of the C standard headers, they are not looked for in the From files
+const STD_TYPES: [&str; 22] = [
+    "bool",
+    "size_t",
+    "ssize_t",
+    "ptrdiff_t",
+    "wchar_t",
+    "va_list",
+    "FILE",
+    "int8_t",
+    "int16_t",
+    "int32_t",
+    "int64_t",
+    "uint8_t",
+    "uint16_t",
+    "uint32_t",
+    "uint64_t",
+    "intptr_t",
+    "uintptr_t",
+    "intmax_t",
+    "uintmax_t",
+    "time_t",
+    "off_t",
+    "max_align_t",
+];
+
+pub(crate) fn code_tokens(text: &str) -> Vec<String> {
+    let mut in_comment = false;
+    let code: Vec<String> = text
+        .lines()
+        .map(|line| strip_comments(line, &mut in_comment))
+        .collect();
+    tokenize_code(&code.join("\n"))
+}
+
+fn tag_keyword(token: &str) -> Option<&'static str> {
+    match token {
+        "struct" => Some("struct"),
+        "union" => Some("union"),
+        "enum" => Some("enum"),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_ident(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// Returns the types a declaration defines, a typedef also defines the tag it declares a body for
+pub fn defined_types(sym: &Symbol) -> Vec<TypeName> {
+    let mut types = Vec::new();
+    if !matches!(
+        sym.kind,
+        SymbolKind::Typedef | SymbolKind::Struct | SymbolKind::Union | SymbolKind::Enum
+    ) {
+        return types;
+    }
+
+    let tokens = code_tokens(&sym.text);
+    for w in tokens.windows(3) {
+        if let Some(keyword) = tag_keyword(&w[0]) {
+            if is_ident(&w[1]) && w[2] == "{" {
+                types.push(TypeName::Tag(keyword, w[1].clone()));
+                break;
+            }
+        }
+    }
+    if sym.kind == SymbolKind::Typedef {
+        types.push(TypeName::Typedef(sym.name.clone()));
+    }
+
+    types
+}
+
+/// Returns the types a declaration uses, without the ones it defines and the standard ones.
+///
+/// Typedef names are told from other identifiers by `typedefs`, or by being followed
+/// by an identifier or `*` outside of initializers and array sizes.
+pub fn used_types(sym: &Symbol, typedefs: &HashSet<&str>) -> Vec<TypeName> {
+    let mut types = Vec::new();
+    if matches!(sym.kind, SymbolKind::Macro | SymbolKind::Other) {
+        return types;
+    }
+
+    let tokens = code_tokens(&sym.text);
+    let defined = defined_types(sym);
+    let mut push = |t: TypeName| {
+        if !defined.contains(&t) && !types.contains(&t) {
+            types.push(t);
+        }
+    };
+
+    // depth of `[ ]` and of an initializer, whose identifiers are values
+    let mut brackets = 0;
+    let mut initializer: Option<i32> = None;
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate() {
+        match t.as_str() {
+            // the body of an inline function
+            "{" if depth == 0 && sym.kind == SymbolKind::Function => break,
+            "[" => brackets += 1,
+            "]" => brackets -= 1,
+            "(" | "{" => depth += 1,
+            ")" | "}" => {
+                depth -= 1;
+                if initializer.is_some_and(|d| depth < d) {
+                    initializer = None;
+                }
+            }
+            "=" => initializer = Some(depth),
+            "," | ";" if initializer == Some(depth) => initializer = None,
+            _ => {}
+        }
+        if brackets > 0 || initializer.is_some() || !is_ident(t) {
+            continue;
+        }
+
+        let prev = i.checked_sub(1).map(|p| tokens[p].as_str());
+        let next = tokens.get(i + 1).map(|t| t.as_str());
+        if let Some(keyword) = tag_keyword(t) {
+            if let Some(name) = next.filter(|n| is_ident(n)) {
+                if tokens.get(i + 2).map(|t| t.as_str()) != Some("{") {
+                    push(TypeName::Tag(keyword, name.to_string()));
+                }
+            }
+            continue;
+        }
+        if BASE_TYPES.contains(&t.as_str())
+            || KEYWORDS.contains(&t.as_str())
+            || STD_TYPES.contains(&t.as_str())
+            || prev.is_some_and(|p| tag_keyword(p).is_some() || p == "." || p == ">")
+        {
+            continue;
+        }
+        if typedefs.contains(t.as_str())
+            || next.is_some_and(|n| {
+                n == "*" || (is_ident(n) && !BASE_TYPES.contains(&n) && tag_keyword(n).is_none())
+            })
+        {
+            push(TypeName::Typedef(t.clone()));
+        }
+    }
+
+    types
+}
+
+/// The declarations pulled in for the types used by synced symbols
+#[derive(Debug, Default)]
+pub struct TypeClosure<'a> {
+    /// Declarations to write before the synced symbols, each after the ones it uses
+    pub symbols: Vec<&'a Symbol>,
+    /// Types no declaration defines, with the name of a symbol using them
+    pub unresolved: Vec<(TypeName, String)>,
+}
+
+/// Returns the declarations of `defs` that `symbols` need through the types they use,
+/// recursively.
+///
+/// `defined` holds the types already written, it is updated with the types of `symbols`
+/// and of the declarations pulled in.
+pub fn type_closure<'a>(
+    symbols: &[&Symbol],
+    defs: &'a [Symbol],
+    defined: &mut HashSet<TypeName>,
+) -> TypeClosure<'a> {
+    let mut index: HashMap<TypeName, &Symbol> = HashMap::new();
+    for def in defs {
+        for t in defined_types(def) {
+            index.entry(t).or_insert(def);
+        }
+    }
+    let typedefs: HashSet<&str> = index
+        .keys()
+        .filter_map(|t| match t {
+            TypeName::Typedef(name) => Some(name.as_str()),
+            TypeName::Tag(..) => None,
+        })
+        .collect();
+
+    for sym in symbols {
+        defined.extend(defined_types(sym));
+    }
+
+    let mut closure = TypeClosure::default();
+    for sym in symbols {
+        for t in used_types(sym, &typedefs) {
+            visit(t, &sym.name, &index, &typedefs, defined, &mut closure);
+        }
+    }
+
+    closure
+}
+
+/// Pulls in the declaration of a type after the ones it uses
+fn visit<'a>(
+    t: TypeName,
+    user: &str,
+    index: &HashMap<TypeName, &'a Symbol>,
+    typedefs: &HashSet<&str>,
+    defined: &mut HashSet<TypeName>,
+    closure: &mut TypeClosure<'a>,
+) {
+    if defined.contains(&t) {
+        return;
+    }
+
+    match index.get(&t) {
+        Some(def) => {
+            defined.extend(defined_types(def));
+            for used in used_types(def, typedefs) {
+                visit(used, &def.name, index, typedefs, defined, closure);
+            }
+            closure.symbols.push(def);
+        }
+        None => {
+            defined.insert(t.clone());
+            closure.unresolved.push((t, user.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{SyncTest, LABEL};
+    use crate::Syncer;
+
+    #[test]
+    fn test_resolve_types() {
+        let test = SyncTest::new(
+            "resolve_types",
+            "void func1(struct test1 *p);\nvoid func2(test1_t *p, uint32_t n);\n",
+        );
+        let types = test.dir.write(
+            "types.h",
+            "typedef unsigned int id_t;\n\n/** test1 */\nstruct test1 {\n    id_t id;\n    \
+             struct test2 *next;\n    bar_t bar;\n};\n\ntypedef struct test1 test1_t;\n",
+        );
+
+        let mut syncer = Syncer::new(
+            vec![types.to_str().unwrap(), &test.from],
+            vec![&test.to],
+            LABEL,
+        );
+        syncer.set_resolve_types(true);
+        syncer.run().unwrap();
+
+        assert!(test.target().contains(
+            "// test1.h: header-sync\ntypedef unsigned int id_t;\n/** test1 */\nstruct test1 {\n\
+             \x20   id_t id;\n    struct test2 *next;\n    bar_t bar;\n};\n\
+             typedef struct test1 test1_t;\n\nvoid func1(struct test1 *p);\n"
+        ));
+        assert_eq!(syncer.unresolved_types(), ["struct test2", "bar_t"]);
+    }
+}
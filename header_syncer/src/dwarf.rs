@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use gimli::{AttributeValue, DwAt, DwTag, Dwarf, Reader};
+use object::RelocationMap;
+
+/// The DWARF sections the declarations are read from
+pub(crate) const SECTIONS: [&str; 5] = [
+    ".debug_info",
+    ".debug_abbrev",
+    ".debug_str",
+    ".debug_line_str",
+    ".debug_str_offsets",
+];
+
+/// The relocations of a DWARF section of an object file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Relocations<'a>(pub &'a RelocationMap);
+
+impl gimli::Relocate for Relocations<'_> {
+    fn relocate_address(&self, offset: usize, value: u64) -> gimli::Result<u64> {
+        Ok(self.0.relocate(offset as u64, value))
+    }
+
+    fn relocate_offset(&self, offset: usize, value: usize) -> gimli::Result<usize> {
+        <usize as gimli::ReaderOffset>::from_u64(self.0.relocate(offset as u64, value as u64))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i64),
+    Str(String),
+    /// Offset of a DIE in `.debug_info`
+    Ref(usize),
+    Flag(bool),
+}
+
+#[derive(Debug)]
+struct Die {
+    tag: DwTag,
+    attrs: Vec<(DwAt, Value)>,
+    children: Vec<usize>,
+}
+
+/// Returns the declarations of the global functions and variables in the DWARF info,
+/// by name, e.g. `int rom_add(int a, int b)` for `rom_add`
+pub(crate) fn declarations<R: Reader<Offset = usize>>(
+    dwarf: &Dwarf<R>,
+) -> gimli::Result<HashMap<String, String>> {
+    let dies = read_dies(dwarf)?;
+    let mut declarations = HashMap::new();
+
+    let mut units: Vec<&usize> = dies.keys().collect();
+    units.sort();
+    for die in units.into_iter().map(|offset| &dies[offset]) {
+        if !matches!(
+            die.tag,
+            gimli::DW_TAG_compile_unit | gimli::DW_TAG_partial_unit
+        ) {
+            continue;
+        }
+        let children = &die.children;
+        for child in children.iter().filter_map(|c| dies.get(c)) {
+            if attr(&dies, child, gimli::DW_AT_external, 0) != Some(&Value::Flag(true)) {
+                continue;
+            }
+            let name = match name(&dies, child) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let declaration = match child.tag {
+                gimli::DW_TAG_subprogram => {
+                    let params = params(&dies, child);
+                    params.and_then(|params| {
+                        declarator(
+                            &dies,
+                            type_of(&dies, child),
+                            &format!("{}({})", name, params),
+                            0,
+                        )
+                    })
+                }
+                gimli::DW_TAG_variable => declarator(&dies, type_of(&dies, child), name, 0),
+                _ => None,
+            };
+            if let Some(declaration) = declaration {
+                declarations.entry(name.to_string()).or_insert(declaration);
+            }
+        }
+    }
+
+    Ok(declarations)
+}
+
+/// Returns an attribute of a DIE, or of the DIE it completes or is an instance of
+fn attr<'a>(
+    dies: &'a HashMap<usize, Die>,
+    die: &'a Die,
+    name: DwAt,
+    depth: usize,
+) -> Option<&'a Value> {
+    if let Some((_, value)) = die.attrs.iter().find(|(n, _)| *n == name) {
+        return Some(value);
+    }
+    if depth > 8 {
+        return None;
+    }
+
+    [gimli::DW_AT_specification, gimli::DW_AT_abstract_origin]
+        .iter()
+        .find_map(|origin| match die.attrs.iter().find(|(n, _)| n == origin) {
+            Some((_, Value::Ref(offset))) => attr(dies, dies.get(offset)?, name, depth + 1),
+            _ => None,
+        })
+}
+
+fn name<'a>(dies: &'a HashMap<usize, Die>, die: &'a Die) -> Option<&'a str> {
+    match attr(dies, die, gimli::DW_AT_name, 0)? {
+        Value::Str(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns the type of a DIE, `None` for `void`
+fn type_of(dies: &HashMap<usize, Die>, die: &Die) -> Option<usize> {
+    match attr(dies, die, gimli::DW_AT_type, 0)? {
+        Value::Ref(offset) => Some(*offset),
+        _ => None,
+    }
+}
+
+/// Returns the parameter list of a function or function type, without the parentheses
+fn params(dies: &HashMap<usize, Die>, die: &Die) -> Option<String> {
+    let mut params = Vec::new();
+    for child in die.children.iter().filter_map(|c| dies.get(c)) {
+        match child.tag {
+            gimli::DW_TAG_formal_parameter => params.push(declarator(
+                dies,
+                type_of(dies, child),
+                name(dies, child).unwrap_or(""),
+                0,
+            )?),
+            gimli::DW_TAG_unspecified_parameters => params.push("...".to_string()),
+            _ => {}
+        }
+    }
+
+    if params.is_empty() && attr(dies, die, gimli::DW_AT_prototyped, 0) == Some(&Value::Flag(true))
+    {
+        params.push("void".to_string());
+    }
+    Some(params.join(", "))
+}
+
+/// Returns the C declaration of `inner` with the given type, `None` if the type can't be
+/// written, e.g. an anonymous struct
+fn declarator(
+    dies: &HashMap<usize, Die>,
+    ty: Option<usize>,
+    inner: &str,
+    depth: usize,
+) -> Option<String> {
+    let join = |base: &str, inner: &str| {
+        if inner.is_empty() {
+            base.to_string()
+        } else {
+            format!("{} {}", base, inner)
+        }
+    };
+    // `*p` needs parentheses before `[]` and `()`
+    let wrap = |inner: &str| {
+        if inner.starts_with('*') {
+            format!("({})", inner)
+        } else {
+            inner.to_string()
+        }
+    };
+
+    let ty = match ty {
+        Some(ty) => ty,
+        None => return Some(join("void", inner)),
+    };
+    let die = dies.get(&ty)?;
+    if depth > 32 {
+        return None;
+    }
+
+    match die.tag {
+        gimli::DW_TAG_base_type | gimli::DW_TAG_typedef => Some(join(name(dies, die)?, inner)),
+        gimli::DW_TAG_structure_type
+        | gimli::DW_TAG_union_type
+        | gimli::DW_TAG_enumeration_type => {
+            let keyword = match die.tag {
+                gimli::DW_TAG_structure_type => "struct",
+                gimli::DW_TAG_union_type => "union",
+                _ => "enum",
+            };
+            Some(join(&format!("{} {}", keyword, name(dies, die)?), inner))
+        }
+        gimli::DW_TAG_pointer_type => {
+            declarator(dies, type_of(dies, die), &format!("*{}", inner), depth + 1)
+        }
+        gimli::DW_TAG_const_type
+        | gimli::DW_TAG_volatile_type
+        | gimli::DW_TAG_restrict_type
+        | gimli::DW_TAG_atomic_type => {
+            let qualifier = match die.tag {
+                gimli::DW_TAG_const_type => "const",
+                gimli::DW_TAG_volatile_type => "volatile",
+                gimli::DW_TAG_restrict_type => "restrict",
+                _ => "_Atomic",
+            };
+            let target = type_of(dies, die);
+            let is_pointer = target
+                .and_then(|t| dies.get(&t))
+                .is_some_and(|t| t.tag == gimli::DW_TAG_pointer_type);
+            if is_pointer {
+                declarator(dies, target, &join(qualifier, inner), depth + 1)
+            } else {
+                Some(format!(
+                    "{} {}",
+                    qualifier,
+                    declarator(dies, target, inner, depth + 1)?
+                ))
+            }
+        }
+        gimli::DW_TAG_array_type => {
+            let mut dims = String::new();
+            for child in die.children.iter().filter_map(|c| dies.get(c)) {
+                if child.tag != gimli::DW_TAG_subrange_type {
+                    continue;
+                }
+                // a negative bound, e.g. -1 for a flexible array, has no size
+                let count = match (
+                    attr(dies, child, gimli::DW_AT_count, 0),
+                    attr(dies, child, gimli::DW_AT_upper_bound, 0),
+                ) {
+                    (Some(Value::Int(count)), _) => Some(*count),
+                    (_, Some(Value::Int(upper))) => upper.checked_add(1),
+                    _ => None,
+                };
+                match count {
+                    Some(count) if count >= 0 => dims.push_str(&format!("[{}]", count)),
+                    _ => dims.push_str("[]"),
+                }
+            }
+            if dims.is_empty() {
+                dims.push_str("[]");
+            }
+            declarator(
+                dies,
+                type_of(dies, die),
+                &format!("{}{}", wrap(inner), dims),
+                depth + 1,
+            )
+        }
+        gimli::DW_TAG_subroutine_type => {
+            let params = params(dies, die)?;
+            declarator(
+                dies,
+                type_of(dies, die),
+                &format!("{}({})", wrap(inner), params),
+                depth + 1,
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Reads the DIEs of all units, by offset, with the attributes that make declarations
+fn read_dies<R: Reader<Offset = usize>>(dwarf: &Dwarf<R>) -> gimli::Result<HashMap<usize, Die>> {
+    let mut dies: HashMap<usize, Die> = HashMap::new();
+    let mut units = dwarf.units();
+
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut parents: Vec<usize> = Vec::new();
+        let mut entries = unit.entries();
+
+        while let Some((delta, entry)) = entries.next_dfs()? {
+            // 1 for the first child of the previous DIE, 0 for its sibling, less going up
+            for _ in delta..1 {
+                parents.pop();
+            }
+            let offset = match entry.offset().to_debug_info_offset(&unit.header) {
+                Some(offset) => offset.0,
+                None => continue,
+            };
+
+            let mut die = Die {
+                tag: entry.tag(),
+                attrs: Vec::new(),
+                children: Vec::new(),
+            };
+            let mut attrs = entry.attrs();
+            while let Some(attr) = attrs.next()? {
+                let value = match (attr.name(), attr.value()) {
+                    (gimli::DW_AT_name, value) => dwarf
+                        .attr_string(&unit, value)?
+                        .to_string_lossy()
+                        .ok()
+                        .map(|name| Value::Str(name.into_owned())),
+                    (
+                        gimli::DW_AT_type
+                        | gimli::DW_AT_specification
+                        | gimli::DW_AT_abstract_origin,
+                        AttributeValue::UnitRef(offset),
+                    ) => offset
+                        .to_debug_info_offset(&unit.header)
+                        .map(|offset| Value::Ref(offset.0)),
+                    (
+                        gimli::DW_AT_type
+                        | gimli::DW_AT_specification
+                        | gimli::DW_AT_abstract_origin,
+                        AttributeValue::DebugInfoRef(offset),
+                    ) => Some(Value::Ref(offset.0)),
+                    (
+                        gimli::DW_AT_external | gimli::DW_AT_prototyped,
+                        AttributeValue::Flag(flag),
+                    ) => Some(Value::Flag(flag)),
+                    (gimli::DW_AT_count | gimli::DW_AT_upper_bound, AttributeValue::Sdata(n)) => {
+                        Some(Value::Int(n))
+                    }
+                    (gimli::DW_AT_count | gimli::DW_AT_upper_bound, value) => value
+                        .udata_value()
+                        .and_then(|n| i64::try_from(n).ok())
+                        .map(Value::Int),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    die.attrs.push((attr.name(), value));
+                }
+            }
+
+            if let Some(parent) = parents.last().and_then(|p| dies.get_mut(p)) {
+                parent.children.push(offset);
+            }
+            dies.insert(offset, die);
+            parents.push(offset);
+        }
+    }
+
+    Ok(dies)
+}
@@ -0,0 +1,280 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use gimli::{DwarfSections, EndianSlice, RelocateReader, RunTimeEndian};
+use object::elf::{STB_GLOBAL, STB_WEAK, STT_FUNC, STT_OBJECT, STV_DEFAULT, STV_PROTECTED};
+use object::read::archive::ArchiveFile;
+use object::{
+    Architecture, Object, ObjectSection, ObjectSymbol, RelocationFlags, RelocationMap, SymbolFlags,
+};
+
+use crate::dwarf::{self, Relocations};
+use crate::lds::c_macro_name;
+
+/// What is written for the symbols of an ELF file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfOutput {
+    /// `extern` declarations, with the prototypes of the DWARF info if there is one
+    Extern,
+    /// `#define <SYM>_ADDR 0x...` address constants
+    Address,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfSymbolKind {
+    Function,
+    Object,
+}
+
+/// A global function or object defined by an ELF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSymbol {
+    pub name: String,
+    pub kind: ElfSymbolKind,
+    pub address: u64,
+    pub size: u64,
+    /// Declaration from the DWARF info without `extern` and `;`, e.g. `int rom_add(int a, int b)`
+    pub declaration: Option<String>,
+}
+
+/// Global symbols of an ELF executable or object file, or of each object of an `ar` archive
+#[derive(Debug, Default)]
+pub struct ElfFile {
+    pub symbols: Vec<ElfSymbol>,
+    /// Why the DWARF info of a file is not used, e.g. relocations of an unknown type
+    pub warnings: Vec<String>,
+}
+
+/// A DWARF section and the relocations of an object file to apply to it
+type DwarfSection<'a> = (Cow<'a, [u8]>, RelocationMap);
+
+impl ElfFile {
+    /// Reads an ELF file or an `ar` archive of ELF files, other archive members are skipped
+    pub fn parse(data: &[u8]) -> Result<ElfFile, String> {
+        let mut file = ElfFile::default();
+
+        if data.starts_with(b"!<arch>\n") {
+            let archive = ArchiveFile::parse(data).map_err(|e| e.to_string())?;
+            for member in archive.members() {
+                let member = member.map_err(|e| e.to_string())?;
+                let name = String::from_utf8_lossy(member.name()).to_string();
+                let member = member
+                    .data(data)
+                    .map_err(|e| format!("member {}: {}", name, e))?;
+                if member.starts_with(b"\x7fELF") {
+                    let warnings = file.warnings.len();
+                    file.read_elf(member)
+                        .map_err(|e| format!("member {}: {}", name, e))?;
+                    for warning in &mut file.warnings[warnings..] {
+                        *warning = format!("member {}: {}", name, warning);
+                    }
+                }
+            }
+        } else {
+            file.read_elf(data)?;
+        }
+
+        file.symbols
+            .sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+        Ok(file)
+    }
+
+    fn read_elf(&mut self, data: &[u8]) -> Result<(), String> {
+        if !data.starts_with(b"\x7fELF") {
+            return Err("not an ELF file".to_string());
+        }
+        let elf = object::File::parse(data).map_err(|e| e.to_string())?;
+
+        // without the DWARF info if its relocations are not known
+        let declarations = match DwarfSections::load(|id| dwarf_section(&elf, id.name())) {
+            Ok(sections) => {
+                let endian = if elf.is_little_endian() {
+                    RunTimeEndian::Little
+                } else {
+                    RunTimeEndian::Big
+                };
+                let dwarf = sections.borrow(|(data, relocations)| {
+                    RelocateReader::new(EndianSlice::new(data, endian), Relocations(relocations))
+                });
+                dwarf::declarations(&dwarf).map_err(|e| format!("bad DWARF info, {}", e))?
+            }
+            Err(message) => {
+                self.warnings.push(format!(
+                    "the DWARF info is not read, {}, the symbols have no prototypes",
+                    message
+                ));
+                HashMap::new()
+            }
+        };
+
+        let symbols = match elf.symbol_table() {
+            Some(_) => elf.symbols(),
+            None => elf.dynamic_symbols(),
+        };
+        for sym in symbols {
+            let SymbolFlags::Elf { st_info, st_other } = sym.flags() else {
+                continue;
+            };
+            // global or weak, default or protected visibility, defined
+            let exported = matches!(st_info >> 4, STB_GLOBAL | STB_WEAK)
+                && matches!(st_other & 3, STV_DEFAULT | STV_PROTECTED)
+                && !sym.is_undefined();
+            let kind = match st_info & 0xf {
+                STT_OBJECT => ElfSymbolKind::Object,
+                STT_FUNC => ElfSymbolKind::Function,
+                _ => continue,
+            };
+            let name = sym.name().map_err(|e| e.to_string())?;
+            if !exported || name.is_empty() || self.symbols.iter().any(|s| s.name == name) {
+                continue;
+            }
+
+            self.symbols.push(ElfSymbol {
+                name: name.to_string(),
+                kind,
+                address: sym.address(),
+                size: sym.size(),
+                declaration: declarations.get(name).cloned(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the C lines for the symbols
+    pub fn to_c_lines(&self, output: ElfOutput) -> Vec<String> {
+        self.symbols
+            .iter()
+            .map(|sym| match (output, &sym.declaration, sym.kind) {
+                (ElfOutput::Address, _, _) => format!(
+                    "#define {}_ADDR 0x{:08x}",
+                    c_macro_name(&sym.name),
+                    sym.address
+                ),
+                (ElfOutput::Extern, Some(declaration), _) => format!("extern {};", declaration),
+                (ElfOutput::Extern, None, ElfSymbolKind::Function) => {
+                    format!("extern void {}();", sym.name)
+                }
+                (ElfOutput::Extern, None, ElfSymbolKind::Object) => {
+                    format!("extern char {}[];", sym.name)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns the data of a DWARF section, empty if the file has none, and the relocations
+/// of an object file, which are absolute references to other sections.
+/// Fails on relocations of an unknown type in the sections the declarations are read from.
+fn dwarf_section<'a>(elf: &object::File<'a>, name: &str) -> Result<DwarfSection<'a>, String> {
+    let section = match elf.section_by_name(name) {
+        Some(section) => section,
+        None => return Ok((Cow::Borrowed(&[]), RelocationMap::default())),
+    };
+    let data = section.uncompressed_data().map_err(|e| e.to_string())?;
+
+    let mut relocations = RelocationMap::default();
+    for (offset, relocation) in section.relocations() {
+        let r_type = match relocation.flags() {
+            RelocationFlags::Elf { r_type } => r_type,
+            _ => 0,
+        };
+        // RISC-V `ADD`, `SUB` and `SET` relocations only give code sizes, which are not read
+        if matches!(
+            elf.architecture(),
+            Architecture::Riscv32 | Architecture::Riscv64
+        ) && matches!(r_type, 33..=40 | 52..=56 | 60 | 61)
+        {
+            continue;
+        }
+        match relocations.add(elf, offset, relocation) {
+            Err(e) if dwarf::SECTIONS.contains(&name) => {
+                return Err(format!(
+                    "relocation type {} in {} at 0x{:x}: {}",
+                    r_type, name, offset, e
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Ok((data, relocations))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{autogen_block, example, TestDir, LABEL};
+    use crate::{ElfFile, ElfOutput, ElfSymbolKind, FromFileType, Syncer};
+
+    #[test]
+    fn test_elf() {
+        let elf = ElfFile::parse(&fs::read(example("rom.elf")).unwrap()).unwrap();
+        let archive = ElfFile::parse(&fs::read(example("librom.a")).unwrap()).unwrap();
+
+        let names: Vec<&str> = elf.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "rom_add",
+                "rom_init",
+                "rom_printf",
+                "rom_reset",
+                "_start",
+                "rom_name",
+                "rom_version",
+                "rom_cfgs"
+            ]
+        );
+        assert_eq!(elf.symbols[0].address, 0x1000);
+        assert_eq!(elf.symbols[0].kind, ElfSymbolKind::Function);
+        assert_eq!(elf.symbols[7].kind, ElfSymbolKind::Object);
+        for (symbols, name, declaration) in [
+            (
+                &elf.symbols,
+                "rom_init",
+                "void rom_init(struct rom_cfg *cfg, void (*cb)(u32))",
+            ),
+            (
+                &archive.symbols,
+                "rom_printf",
+                "int rom_printf(const char *fmt, ...)",
+            ),
+            (&archive.symbols, "rom_name", "const char *const rom_name"),
+            (&archive.symbols, "rom_cfgs", "struct rom_cfg rom_cfgs[4]"),
+        ] {
+            let sym = symbols.iter().find(|s| s.name == name).unwrap();
+            assert_eq!(sym.declaration.as_deref(), Some(declaration));
+        }
+        assert!(ElfFile::parse(b"not an ELF file").is_err());
+
+        // relocations of a machine that is not known, here VAX
+        let mut data = fs::read(example("librom.a")).unwrap();
+        let member = data.windows(4).position(|w| w == b"\x7fELF").unwrap();
+        data[member + 18..member + 20].copy_from_slice(&75u16.to_le_bytes());
+        let vax = ElfFile::parse(&data).unwrap();
+        assert_eq!(vax.symbols.len(), archive.symbols.len());
+        assert!(vax.symbols.iter().all(|s| s.declaration.is_none()));
+        assert_eq!(vax.warnings.len(), 1);
+        assert!(vax.warnings[0].contains("the DWARF info is not read"));
+
+        let dir = TestDir::new("elf");
+        let to = dir.write("api.h", autogen_block("", ""));
+        let mut syncer = Syncer::new(vec![&example("rom.elf")], vec![to.to_str().unwrap()], LABEL);
+        syncer.set_type_of_form(FromFileType::Elf);
+        syncer.set_elf_output(ElfOutput::Address);
+        syncer.set_keep_symbols(vec!["rom_*"]);
+        syncer.set_ignore_symbols(vec!["rom_name", "rom_printf"]);
+        syncer.run().unwrap();
+        assert_eq!(
+            fs::read_to_string(&to).unwrap(),
+            autogen_block(
+                "",
+                "// rom.elf\n#define ROM_ADD_ADDR 0x00001000\n#define ROM_INIT_ADDR 0x0000101c\n\
+                 #define ROM_RESET_ADDR 0x000010a2\n#define ROM_VERSION_ADDR 0x000011a0\n\
+                 #define ROM_CFGS_ADDR 0x000011b0\n"
+            )
+        );
+    }
+}
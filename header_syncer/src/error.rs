@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a sync or restore failed, each variant names the file or rule at fault
+#[derive(Debug)]
+pub enum SyncError {
+    /// A file could not be read or written
+    Io { path: String, source: io::Error },
+    /// A From header could not be preprocessed or parsed
+    Parse {
+        path: String,
+        source: lang_c::driver::Error,
+    },
+    /// The `#if` blocks of a From header could not be evaluated
+    Preprocess { path: String, message: String },
+    /// An ELF From file or archive could not be read
+    Elf { path: String, message: String },
+    /// The C preprocessor can't be run
+    Toolchain { command: String, message: String },
+    /// A From or To file can't be decoded, or the synced text can't be encoded in the To file's encoding
+    Encoding { path: String, message: String },
+    /// The address source of the `PROVIDE` lines could not be read
+    Addresses { path: String, message: String },
+    /// An ignore or keep rule is not a valid pattern
+    Pattern { rule: String, source: regex::Error },
+    /// A mark rule is not `<pattern>=<mark>` or its pattern is invalid
+    Mark { rule: String, message: String },
+    /// A jump table rule is not `[<region>=]<name>@<address>`
+    JumpTable { rule: String, message: String },
+    /// Two From files declare a symbol of a region differently, at the `path:line` locations
+    Conflict {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    /// A job file is not valid
+    Job { path: String, message: String },
+    /// A To file has no autogen block for the region
+    NoBlock { path: String, region: String },
+    /// A To file has no saved generation to restore
+    NoHistory { path: String, region: String },
+}
+
+impl SyncError {
+    pub(crate) fn io(path: impl AsRef<str>, source: io::Error) -> SyncError {
+        SyncError::Io {
+            path: path.as_ref().to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Io { path, source } => write!(f, "{}: {}", path, source),
+            SyncError::Parse { path, source } => write!(f, "{} parse failed, {}", path, source),
+            SyncError::Preprocess { path, message } => {
+                write!(f, "{} preprocessing failed, {}", path, message)
+            }
+            SyncError::Elf { path, message } => write!(f, "{} read failed, {}", path, message),
+            SyncError::Toolchain { command, message } => {
+                write!(f, "preprocessor {} can't be run, {}", command, message)
+            }
+            SyncError::Encoding { path, message } => {
+                write!(f, "{} can't be synced, {}", path, message)
+            }
+            SyncError::Addresses { path, message } => {
+                write!(f, "address source {} is invalid, {}", path, message)
+            }
+            SyncError::Pattern { rule, source } => {
+                write!(f, "symbol pattern {} is invalid, {}", rule, source)
+            }
+            SyncError::Mark { rule, message } => {
+                write!(f, "mark symbol {} is invalid, {}", rule, message)
+            }
+            SyncError::JumpTable { rule, message } => {
+                write!(f, "jump table {} is invalid, {}", rule, message)
+            }
+            SyncError::Conflict {
+                symbol,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} at {} conflicts with its declaration at {}",
+                symbol, second, first
+            ),
+            SyncError::Job { path, message } => write!(f, "{} is invalid, {}", path, message),
+            SyncError::NoBlock { path, region } if region.is_empty() => {
+                write!(f, "{} has no autogen block", path)
+            }
+            SyncError::NoBlock { path, region } => {
+                write!(f, "{} has no autogen block {}", path, region)
+            }
+            SyncError::NoHistory { path, region } if region.is_empty() => {
+                write!(f, "{} has no history", path)
+            }
+            SyncError::NoHistory { path, region } => {
+                write!(f, "{} has no history for {}", path, region)
+            }
+        }
+    }
+}
+
+impl Error for SyncError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SyncError::Io { source, .. } => Some(source),
+            SyncError::Parse { source, .. } => Some(source),
+            SyncError::Pattern { source, .. } => Some(source),
+            SyncError::Preprocess { .. }
+            | SyncError::Elf { .. }
+            | SyncError::Addresses { .. }
+            | SyncError::Encoding { .. }
+            | SyncError::Toolchain { .. }
+            | SyncError::Mark { .. }
+            | SyncError::JumpTable { .. }
+            | SyncError::Conflict { .. }
+            | SyncError::Job { .. }
+            | SyncError::NoBlock { .. }
+            | SyncError::NoHistory { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::SyncTest;
+    use crate::SyncError;
+
+    #[test]
+    fn test_sync_errors() {
+        let test = SyncTest::new("sync_errors", "");
+        fs::remove_file(&test.from).unwrap();
+
+        let mut syncer = test.syncer();
+        match syncer.run() {
+            Err(SyncError::Io { path, .. }) => assert_eq!(path, test.from),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        test.set_code("void v1(void);\n");
+        syncer.set_ignore_symbols(vec!["/(/"]);
+        assert!(matches!(syncer.run(), Err(SyncError::Pattern { .. })));
+
+        syncer.set_ignore_symbols(vec![]);
+        syncer.run().unwrap();
+        let mut names: Vec<String> = fs::read_dir(&test.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["api.h", "test1.h"]);
+    }
+}
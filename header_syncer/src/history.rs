@@ -0,0 +1,195 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the directory that keeps the history, next to the To file
+pub const HISTORY_DIR: &str = ".header_syncer";
+
+/// Previous autogen blocks of a To file.
+///
+/// Each generation is stored as `.header_syncer/<file name>/<UTC time>.block`,
+/// so the generations sort by the time they were replaced.
+/// Blocks of a named region go to `.header_syncer/<file name>/<region>/`.
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    /// Fails if the region name is not a plain directory name
+    pub fn new(target: &str, region: &str) -> io::Result<History> {
+        check_name("region", region)?;
+        let target = Path::new(target);
+        let name = target
+            .file_name()
+            .map_or("target".into(), |name| name.to_string_lossy());
+
+        Ok(History {
+            dir: target
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(HISTORY_DIR)
+                .join(name.as_ref())
+                .join(region),
+        })
+    }
+
+    /// Returns the saved generations, oldest first
+    pub fn generations(&self) -> Vec<String> {
+        let mut generations: Vec<String> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| name.strip_suffix(".block"))
+                        .map(|name| name.to_string())
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        generations.sort_by(|a, b| generation_key(a).cmp(&generation_key(b)));
+        generations
+    }
+
+    /// Saves a block as a new generation and returns its name
+    pub fn save(&self, block: &str) -> io::Result<String> {
+        fs::create_dir_all(&self.dir)?;
+
+        let time = format_utc(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        );
+        let mut generation = time.clone();
+        let mut n = 0;
+        while self.path(&generation).exists() {
+            n += 1;
+            generation = format!("{}-{}", time, n);
+        }
+
+        fs::write(self.path(&generation), block)?;
+        Ok(generation)
+    }
+
+    pub fn load(&self, generation: &str) -> io::Result<String> {
+        check_name("generation", generation)?;
+        fs::read_to_string(self.path(generation))
+    }
+
+    /// Removes all but the newest `keep` generations
+    pub fn prune(&self, keep: usize) -> io::Result<()> {
+        let generations = self.generations();
+        let remove = generations.len().saturating_sub(keep);
+
+        for generation in &generations[..remove] {
+            fs::remove_file(self.path(generation))?;
+        }
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path(&self, generation: &str) -> PathBuf {
+        self.dir.join(format!("{}.block", generation))
+    }
+}
+
+/// Returns the time and the count of a generation, `<time>-<n>` is the n-th saved in a second
+fn generation_key(generation: &str) -> (&str, u64) {
+    match generation.match_indices('-').nth(1) {
+        Some((i, _)) => (&generation[..i], generation[i + 1..].parse().unwrap_or(0)),
+        None => (generation, 0),
+    }
+}
+
+/// Fails if a name given for a region or generation would leave its directory
+fn check_name(kind: &str, name: &str) -> io::Result<()> {
+    if name.contains(['/', '\\']) || name.contains("..") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} {} is not a valid file name", kind, name),
+        ));
+    }
+    Ok(())
+}
+
+/// Formats seconds since the Unix epoch as `YYYYMMDD-HHMMSS` in UTC
+pub fn format_utc(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{autogen_block, SyncTest};
+    use crate::{format_utc, History};
+
+    #[test]
+    fn test_history_and_restore() {
+        let test = SyncTest::new("history_and_restore", "void v1(void);\n");
+        let to = test.to.as_str();
+        fs::write(to, autogen_block("", "void v0(void);\n")).unwrap();
+
+        let mut syncer = test.syncer();
+        syncer.set_history(1);
+        syncer.run().unwrap();
+        test.set_code("void v2(void);\n");
+        syncer.run().unwrap();
+
+        let history = History::new(to, "").unwrap();
+        let generations = history.generations();
+        assert_eq!(generations.len(), 1);
+        assert_eq!(
+            history.load(&generations[0]).unwrap(),
+            "// test1.h: header-sync\nvoid v1(void);\n"
+        );
+
+        assert_eq!(syncer.restore(to, "", None).unwrap(), generations[0]);
+        assert!(test.target().contains("\nvoid v1(void);\n"));
+        assert_eq!(history.generations().len(), 1);
+        assert!(syncer.restore(to, "", Some("19700101-000000")).is_err());
+        assert!(syncer.restore(to, "../api", None).is_err());
+        assert!(syncer.restore(to, "", Some("../api")).is_err());
+
+        let history = History::new(to, "same_second").unwrap();
+        fs::create_dir_all(history.dir()).unwrap();
+        for generation in ["20260101-000000-10", "20260101-000000", "20260101-000000-2"] {
+            fs::write(history.path(generation), "").unwrap();
+        }
+        assert_eq!(
+            history.generations(),
+            ["20260101-000000", "20260101-000000-2", "20260101-000000-10"]
+        );
+
+        assert_eq!(format_utc(0), "19700101-000000");
+        assert_eq!(format_utc(1700000000), "20231114-221320");
+    }
+}
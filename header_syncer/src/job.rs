@@ -0,0 +1,435 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{Document, Item, Table};
+
+use crate::text::find_encoding;
+use crate::{
+    ConditionalMode, ElfOutput, FromFileType, MapOutput, ParseMode, Preprocessor, SyncError,
+    Syncer, Toolchain,
+};
+
+/// One sync of a job file, e.g.
+///
+/// ```toml
+/// [[job]]
+/// name = "gpio"
+/// from = ["drivers/gpio.h", "drivers/gpio_ll.h"]
+/// to = ["api/api.h"]
+/// type = "header"          # or "gnu_lds", "elf", "gnu_map"
+/// parse_mode = "text"      # or "ast"
+/// label = "/* header-sync */"
+/// class_name = "gpio"
+/// ignore = ["tmp_*"]
+/// keep = ["gpio_*"]
+/// mark = ["gpio_*=ROM_API"]
+/// jump_table = ["gpio=gpio_api@0x00010000"]
+/// provide_addresses = "rom.elf" # or a map or CSV file
+/// compress = false
+/// tidy = false
+/// elf_output = "extern"    # or "address"
+/// map_output = "defines"   # or "table"
+/// resolve_types = false
+/// incremental = false
+/// stamp = false
+/// history = 10          # 0 by default, keeps no history
+/// define = ["CONFIG_UART", "CONFIG_UART_NUM=2"]
+/// undefine = ["CONFIG_DEBUG"]
+/// include = ["inc"]
+/// conditionals = "evaluate" # or "prune"
+/// encoding = "gbk"
+/// cpp = "arm-none-eabi-gcc -E" # given the define, undefine and include options
+/// tool_path = ["/opt/gcc-arm/bin"]
+/// ```
+///
+/// Only `from` and `to` are required, relative paths are relative to the job file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncJob {
+    pub name: String,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub type_of_from: String,
+    pub parse_mode: String,
+    pub label: String,
+    pub class_name: String,
+    pub ignore: Vec<String>,
+    pub keep: Vec<String>,
+    pub mark: Vec<String>,
+    pub jump_table: Vec<String>,
+    /// `""` if no `PROVIDE` lines are written
+    pub provide_addresses: String,
+    pub compress: bool,
+    pub tidy: bool,
+    pub elf_output: String,
+    pub map_output: String,
+    pub resolve_types: bool,
+    pub incremental: bool,
+    pub stamp: bool,
+    pub history: usize,
+    pub define: Vec<String>,
+    pub undefine: Vec<String>,
+    pub include: Vec<String>,
+    /// `""` if the `#if` blocks are not evaluated
+    pub conditionals: String,
+    /// `""` if the files are UTF-8
+    pub encoding: String,
+    /// `""` for `gcc -E`
+    pub cpp: String,
+    /// Directories to search `cpp` in before `PATH`
+    pub tool_path: Vec<String>,
+}
+
+impl SyncJob {
+    /// Reads the `[[job]]` tables of a TOML job file, two jobs writing the same
+    /// autogen block of a To file are an error as the last one would overwrite the other
+    pub fn load(path: &str) -> Result<Vec<SyncJob>, SyncError> {
+        let text = fs::read_to_string(path).map_err(|e| SyncError::io(path, e))?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let error = |message| SyncError::Job {
+            path: path.to_string(),
+            message,
+        };
+        let jobs = SyncJob::parse(&text, dir).map_err(error)?;
+
+        // job, To file and region of each autogen block written
+        let mut written: Vec<(String, PathBuf, String)> = Vec::new();
+        for (i, job) in jobs.iter().enumerate() {
+            let name = match job.name.as_str() {
+                "" => format!("job {}", i + 1),
+                name => format!("job {}", name),
+            };
+            for (to, region) in job.syncer().targets()? {
+                let target = fs::canonicalize(&to).unwrap_or(PathBuf::from(&to));
+                if let Some((other, _, _)) = written
+                    .iter()
+                    .find(|(_, t, r)| *t == target && *r == region)
+                {
+                    let block = match region.as_str() {
+                        "" => "unnamed autogen block".to_string(),
+                        region => format!("autogen block {}", region),
+                    };
+                    return Err(error(format!(
+                        "{} and {} both write the {} of {}",
+                        other, name, block, to
+                    )));
+                }
+                written.push((name.clone(), target, region));
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    /// Parses a job file, relative paths are joined to `dir`
+    pub fn parse(text: &str, dir: &Path) -> Result<Vec<SyncJob>, String> {
+        let doc = text.parse::<Document>().map_err(|e| e.to_string())?;
+        let tables = match doc.get("job") {
+            Some(item) => item
+                .as_array_of_tables()
+                .ok_or("`job` is not an array of tables, use [[job]]")?,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut jobs = Vec::new();
+        for (i, table) in tables.iter().enumerate() {
+            let job =
+                SyncJob::from_table(table, dir).map_err(|e| format!("job {}: {}", i + 1, e))?;
+            if !job.name.is_empty() && jobs.iter().any(|j: &SyncJob| j.name == job.name) {
+                return Err(format!("job {}: name {} is used twice", i + 1, job.name));
+            }
+            jobs.push(job);
+        }
+
+        Ok(jobs)
+    }
+
+    fn from_table(table: &Table, dir: &Path) -> Result<SyncJob, String> {
+        const KEYS: [&str; 27] = [
+            "name",
+            "from",
+            "to",
+            "type",
+            "parse_mode",
+            "label",
+            "class_name",
+            "ignore",
+            "keep",
+            "mark",
+            "jump_table",
+            "provide_addresses",
+            "compress",
+            "tidy",
+            "elf_output",
+            "map_output",
+            "resolve_types",
+            "incremental",
+            "stamp",
+            "history",
+            "define",
+            "undefine",
+            "include",
+            "conditionals",
+            "encoding",
+            "cpp",
+            "tool_path",
+        ];
+        if let Some((key, _)) = table.iter().find(|(key, _)| !KEYS.contains(key)) {
+            return Err(format!("unknown key {}", key));
+        }
+
+        let path = |p: String| dir.join(p).to_string_lossy().to_string();
+        let from: Vec<String> = get_strings(table, "from")?.into_iter().map(path).collect();
+        let to: Vec<String> = get_strings(table, "to")?.into_iter().map(path).collect();
+        if from.is_empty() || to.is_empty() {
+            return Err("`from` and `to` are required".to_string());
+        }
+
+        let job = SyncJob {
+            name: get_str(table, "name")?.unwrap_or_default(),
+            from,
+            to,
+            type_of_from: get_str(table, "type")?.unwrap_or("header".to_string()),
+            parse_mode: get_str(table, "parse_mode")?.unwrap_or("text".to_string()),
+            label: get_str(table, "label")?.unwrap_or("/* header-sync */".to_string()),
+            class_name: get_str(table, "class_name")?.unwrap_or_default(),
+            ignore: get_strings(table, "ignore")?,
+            keep: get_strings(table, "keep")?,
+            mark: get_strings(table, "mark")?,
+            jump_table: get_strings(table, "jump_table")?,
+            provide_addresses: get_str(table, "provide_addresses")?
+                .map(path)
+                .unwrap_or_default(),
+            compress: get_bool(table, "compress")?,
+            tidy: get_bool(table, "tidy")?,
+            elf_output: get_str(table, "elf_output")?.unwrap_or("extern".to_string()),
+            map_output: get_str(table, "map_output")?.unwrap_or("defines".to_string()),
+            resolve_types: get_bool(table, "resolve_types")?,
+            incremental: get_bool(table, "incremental")?,
+            stamp: get_bool(table, "stamp")?,
+            history: match table.get("history") {
+                Some(item) => item
+                    .as_integer()
+                    .and_then(|n| usize::try_from(n).ok())
+                    .ok_or("`history` is not a count")?,
+                None => 0,
+            },
+            define: get_strings(table, "define")?,
+            undefine: get_strings(table, "undefine")?,
+            include: get_strings(table, "include")?
+                .into_iter()
+                .map(path)
+                .collect(),
+            conditionals: get_str(table, "conditionals")?.unwrap_or_default(),
+            encoding: get_str(table, "encoding")?.unwrap_or_default(),
+            cpp: get_str(table, "cpp")?.unwrap_or_default(),
+            tool_path: get_strings(table, "tool_path")?
+                .into_iter()
+                .map(path)
+                .collect(),
+        };
+        if !matches!(
+            job.type_of_from.as_str(),
+            "header" | "gnu_lds" | "elf" | "gnu_map"
+        ) {
+            return Err(format!("unknown type {}", job.type_of_from));
+        }
+        if !matches!(job.parse_mode.as_str(), "text" | "ast") {
+            return Err(format!("unknown parse_mode {}", job.parse_mode));
+        }
+        if !matches!(job.elf_output.as_str(), "extern" | "address") {
+            return Err(format!("unknown elf_output {}", job.elf_output));
+        }
+        if !matches!(job.map_output.as_str(), "defines" | "table") {
+            return Err(format!("unknown map_output {}", job.map_output));
+        }
+        if !matches!(job.conditionals.as_str(), "" | "evaluate" | "prune") {
+            return Err(format!("unknown conditionals {}", job.conditionals));
+        }
+        if !job.encoding.is_empty() {
+            find_encoding(&job.encoding)?;
+        }
+
+        Ok(job)
+    }
+
+    /// Returns a [`Syncer`] set up for the job
+    pub fn syncer(&self) -> Syncer {
+        let mut syncer = Syncer::new(
+            self.from.iter().map(|s| s.as_str()).collect(),
+            self.to.iter().map(|s| s.as_str()).collect(),
+            &self.label,
+        );
+
+        match self.type_of_from.as_str() {
+            "gnu_lds" => syncer.set_type_of_form(FromFileType::GnuLinkScript),
+            "elf" => syncer.set_type_of_form(FromFileType::Elf),
+            "gnu_map" => syncer.set_type_of_form(FromFileType::GnuMap),
+            _ => {}
+        }
+        if self.elf_output == "address" {
+            syncer.set_elf_output(ElfOutput::Address);
+        }
+        if self.map_output == "table" {
+            syncer.set_map_output(MapOutput::Table);
+        }
+        if self.parse_mode == "ast" {
+            syncer.set_parse_mode(ParseMode::Ast);
+        }
+        syncer.set_class_name(&self.class_name);
+        syncer.set_ignore_symbols(self.ignore.iter().map(|s| s.as_str()).collect());
+        syncer.set_keep_symbols(self.keep.iter().map(|s| s.as_str()).collect());
+        syncer.set_mark_symbols(self.mark.iter().map(|s| s.as_str()).collect());
+        syncer.set_jump_tables(self.jump_table.iter().map(|s| s.as_str()).collect());
+        if !self.provide_addresses.is_empty() {
+            syncer.set_provide_addresses(&self.provide_addresses);
+        }
+        syncer.set_compress(self.compress);
+        syncer.set_tidy(self.tidy);
+        syncer.set_resolve_types(self.resolve_types);
+        syncer.set_incremental(self.incremental);
+        syncer.set_stamp(self.stamp);
+        syncer.set_history(self.history);
+        if !(self.define.is_empty()
+            && self.undefine.is_empty()
+            && self.include.is_empty()
+            && self.conditionals.is_empty())
+        {
+            let mut pp = Preprocessor::new(match self.conditionals.as_str() {
+                "prune" => ConditionalMode::Prune,
+                _ => ConditionalMode::Evaluate,
+            });
+            self.define.iter().for_each(|d| pp.define(d));
+            self.undefine.iter().for_each(|u| pp.undefine(u));
+            self.include.iter().for_each(|i| pp.include_path(i));
+            syncer.set_preprocessor(pp);
+        }
+        if !(self.cpp.is_empty() && self.tool_path.is_empty()) {
+            let mut toolchain = Toolchain::new(&self.cpp);
+            self.tool_path.iter().for_each(|p| toolchain.add_path(p));
+            syncer.set_toolchain(toolchain);
+        }
+        if !self.encoding.is_empty() {
+            // checked when the job was read
+            let _ = syncer.set_encoding(&self.encoding);
+        }
+
+        syncer
+    }
+}
+
+fn get_str(table: &Table, key: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        Some(item) => match item.as_str() {
+            Some(s) => Ok(Some(s.to_string())),
+            None => Err(format!("`{}` is not a string", key)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns a string or an array of strings as a list
+fn get_strings(table: &Table, key: &str) -> Result<Vec<String>, String> {
+    let item = match table.get(key) {
+        Some(item) => item,
+        None => return Ok(Vec::new()),
+    };
+    if let Some(s) = item.as_str() {
+        return Ok(vec![s.to_string()]);
+    }
+
+    item.as_array()
+        .and_then(|array| {
+            array
+                .iter()
+                .map(|v| v.as_str().map(|s| s.to_string()))
+                .collect::<Option<Vec<String>>>()
+        })
+        .ok_or(format!("`{}` is not a list of strings", key))
+}
+
+fn get_bool(table: &Table, key: &str) -> Result<bool, String> {
+    table
+        .get(key)
+        .map_or(Some(false), Item::as_bool)
+        .ok_or(format!("`{}` is not true or false", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::test_util::{example, sync_region, TestDir, LABEL};
+    use crate::{SyncError, SyncJob};
+
+    #[test]
+    fn test_sync_jobs() {
+        let jobs = SyncJob::load(&example("sync.toml")).unwrap();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].name, "api");
+        assert_eq!(
+            jobs[0].from,
+            [
+                dir.join("test1.h").to_string_lossy(),
+                dir.join("test2.h").to_string_lossy()
+            ]
+        );
+        assert_eq!(jobs[0].to, [dir.join("api.h").to_string_lossy()]);
+        assert_eq!(jobs[0].label, LABEL);
+        assert_eq!(jobs[0].history, 0);
+        assert_eq!(jobs[1].type_of_from, "gnu_lds");
+        assert_eq!(jobs[1].ignore, ["rom_memcpy"]);
+        assert!(jobs[1].compress);
+        assert_eq!(jobs[1].history, 5);
+
+        let dir = Path::new("");
+        assert!(SyncJob::parse("[[job]]\nfrom = \"a.h\"\n", dir).is_err());
+        assert!(SyncJob::parse(
+            "[[job]]\nfrom = \"a.h\"\nto = \"b.h\"\nlable = \"x\"\n",
+            dir
+        )
+        .is_err());
+        assert!(SyncJob::parse("[[job]]\nfrom = \"a.h\"\nto = \"b.h\"\nstamp = 1\n", dir).is_err());
+        assert!(SyncJob::parse("job = 1\n", dir).is_err());
+        let unnamed = "[[job]]\nfrom = \"a.h\"\nto = \"b.h\"\n";
+        assert_eq!(SyncJob::parse(&unnamed.repeat(2), dir).unwrap().len(), 2);
+        let named = "[[job]]\nname = \"a\"\nfrom = \"a.h\"\nto = \"b.h\"\n";
+        assert!(SyncJob::parse(&named.repeat(2), dir).is_err());
+    }
+
+    #[test]
+    fn test_job_targets() {
+        let dir = TestDir::new("job_targets");
+        dir.write("a.h", sync_region("void a(void);\n"));
+        dir.write(
+            "b.h",
+            "/* header-sync:b start */\nvoid b(void);\n/* header-sync:b end */\n",
+        );
+        let job = |name: &str, from: &str, to: &str| {
+            format!(
+                "[[job]]\nname = \"{}\"\nfrom = \"{}\"\nto = \"{}\"\n",
+                name, from, to
+            )
+        };
+        let config = dir.join("sync.toml");
+        let config = config.to_str().unwrap();
+
+        dir.write(
+            "sync.toml",
+            job("a", "a.h", "api.h") + &job("b", "b.h", "api.h"),
+        );
+        assert_eq!(SyncJob::load(config).unwrap().len(), 2);
+
+        dir.write(
+            "sync.toml",
+            job("a", "a.h", "api.h") + &job("c", "a.h", "./api.h"),
+        );
+        match SyncJob::load(config) {
+            Err(SyncError::Job { message, .. }) => assert!(
+                message.starts_with("job a and job c both write the unnamed autogen block of ")
+            ),
+            res => panic!("{:?}", res),
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use std::path::Path;
+
+use crate::lds::c_macro_name;
+use crate::{compress_declaration, Symbol, SymbolKind};
+
+/// Turns the prototypes of a sync region into a jump table, the rule is
+/// `[<region>=]<name>@<address>`, e.g. `rom_api@0x00010000` for the unnamed region
+/// or `gpio=gpio_api@0x00010400` for the region named `gpio`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTableRule {
+    rule: String,
+    region: String,
+    name: String,
+    address: u64,
+}
+
+impl JumpTableRule {
+    pub fn new(rule: &str) -> Result<JumpTableRule, String> {
+        let (region, table) = rule.split_once('=').unwrap_or(("", rule));
+        let (name, address) = table
+            .split_once('@')
+            .filter(|(name, _)| is_c_ident(name))
+            .ok_or("expected [<region>=]<name>@<address>".to_string())?;
+        let address = match address.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => address.parse(),
+        }
+        .map_err(|_| format!("address {} is not a number", address))?;
+
+        Ok(JumpTableRule {
+            rule: rule.to_string(),
+            region: region.to_string(),
+            name: name.to_string(),
+            address,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.rule
+    }
+
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    /// Returns an empty table for the rule
+    pub fn table(&self) -> JumpTable {
+        JumpTable {
+            name: self.name.clone(),
+            address: self.address,
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// Part of a jump table written into a To file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpTableOutput {
+    /// Index `#define`s and the struct of function pointers
+    Header,
+    /// The table filled in with the functions
+    Source,
+    /// A GNU ld output section placing the table at its address
+    LinkScript,
+}
+
+impl JumpTableOutput {
+    /// Returns the part for a To file by its extension, `.c`, `.ld` or `.lds`, else a header
+    pub fn for_path(path: &str) -> JumpTableOutput {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("c") => JumpTableOutput::Source,
+            Some("ld" | "lds") => JumpTableOutput::LinkScript,
+            _ => JumpTableOutput::Header,
+        }
+    }
+}
+
+/// A function of a jump table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpEntry {
+    pub name: String,
+    /// The struct member, e.g. `int (*rom_add)(int a, int b);`
+    pub member: String,
+}
+
+/// A struct of function pointers at a fixed address, the contract between a ROM and its patches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    pub name: String,
+    pub address: u64,
+    /// The functions in table order, the index of an entry is its position,
+    /// see [`JumpTable::keep_indices`]
+    pub entries: Vec<JumpEntry>,
+}
+
+/// Storage classes and function specifiers that can't be part of a struct member
+const NOT_MEMBER: [&str; 6] = [
+    "extern",
+    "static",
+    "inline",
+    "__inline",
+    "__inline__",
+    "_Noreturn",
+];
+
+impl JumpTable {
+    /// Adds a function prototype to the table, other symbols and inline functions are not added.
+    /// Returns whether it was added.
+    pub fn push(&mut self, sym: &Symbol) -> bool {
+        if sym.kind != SymbolKind::Function || self.entries.iter().any(|e| e.name == sym.name) {
+            return false;
+        }
+        let decl = compress_declaration(&sym.text);
+        if decl.contains('{') {
+            return false;
+        }
+
+        let mut decl = decl.trim_end_matches(';').trim_end();
+        while let Some(rest) = NOT_MEMBER
+            .iter()
+            .find_map(|keyword| decl.strip_prefix(keyword))
+            .filter(|rest| rest.starts_with(' '))
+        {
+            decl = rest.trim_start();
+        }
+
+        let pos = match find_declarator(decl, &sym.name) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        let params = decl[pos + sym.name.len()..].trim_start();
+        self.entries.push(JumpEntry {
+            name: sym.name.clone(),
+            member: format!("{}(*{}){};", &decl[..pos], sym.name, params),
+        });
+        true
+    }
+
+    /// Returns the entry indices a header part of the table was written with,
+    /// by the macro name of each entry, e.g. `ROM_ADD` for `#define ROM_API_ROM_ADD_IDX 0`
+    pub fn written_indices(&self, lines: &[&str]) -> Vec<(String, usize)> {
+        let prefix = format!("{}_", c_macro_name(&self.name));
+        lines
+            .iter()
+            .filter_map(|line| {
+                let mut words = line.strip_prefix("#define ")?.split_whitespace();
+                let entry = words.next()?.strip_prefix(&prefix)?.strip_suffix("_IDX")?;
+                let index = words.next()?.parse().ok()?;
+                Some((entry.to_string(), index))
+            })
+            .collect()
+    }
+
+    /// Keeps the indices the entries were written with, the new entries go after them.
+    /// Fails if an entry written before is gone or would move, since the ROM built with
+    /// the table can't be changed.
+    pub fn keep_indices(&mut self, written: &[(String, usize)]) -> Result<(), String> {
+        let mut written = written.to_vec();
+        written.sort_by_key(|(_, index)| *index);
+
+        let mut entries = Vec::new();
+        for (i, (name, index)) in written.iter().enumerate() {
+            let pos = self
+                .entries
+                .iter()
+                .position(|e| c_macro_name(&e.name) == *name)
+                .ok_or(format!(
+                    "{} with index {} is gone from {}",
+                    name, index, self.name
+                ))?;
+            if *index != i {
+                return Err(format!(
+                    "{} would move from index {} to {} in {}",
+                    name, index, i, self.name
+                ));
+            }
+            entries.push(self.entries.remove(pos));
+        }
+        entries.append(&mut self.entries);
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// Returns the C or GNU ld lines for a part of the table
+    pub fn to_c_lines(&self, output: JumpTableOutput) -> Vec<String> {
+        let macro_name = c_macro_name(&self.name);
+        let section = format!(".{}", self.name);
+        let mut lines = Vec::new();
+
+        match output {
+            JumpTableOutput::Header => {
+                lines.push(format!(
+                    "#define {}_ADDR 0x{:08x}",
+                    macro_name, self.address
+                ));
+                for (i, entry) in self.entries.iter().enumerate() {
+                    lines.push(format!(
+                        "#define {}_{}_IDX {}",
+                        macro_name,
+                        c_macro_name(&entry.name),
+                        i
+                    ));
+                }
+                lines.push(format!(
+                    "#define {}_COUNT {}",
+                    macro_name,
+                    self.entries.len()
+                ));
+                lines.push(String::new());
+                lines.push(format!("typedef struct {} {{", self.name));
+                for entry in &self.entries {
+                    lines.push(format!("    {}", entry.member));
+                }
+                lines.push(format!("}} {}_t;", self.name));
+                lines.push(String::new());
+                lines.push(format!(
+                    "#define {}_TABLE ((const {}_t *){}_ADDR)",
+                    macro_name, self.name, macro_name
+                ));
+            }
+            JumpTableOutput::Source => {
+                lines.push(format!(
+                    "__attribute__((section(\"{}\"), used)) const {}_t {} = {{",
+                    section, self.name, self.name
+                ));
+                for entry in &self.entries {
+                    lines.push(format!("    .{} = {},", entry.name, entry.name));
+                }
+                lines.push("};".to_string());
+            }
+            JumpTableOutput::LinkScript => {
+                lines.push(format!("{} 0x{:08x} :", section, self.address));
+                lines.push("{".to_string());
+                lines.push(format!("    KEEP(*({}))", section));
+                lines.push("}".to_string());
+            }
+        }
+
+        lines
+    }
+}
+
+/// Returns the offset of the function name followed by its parameter list
+fn find_declarator(decl: &str, name: &str) -> Option<usize> {
+    let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    decl.match_indices(name).map(|(pos, _)| pos).find(|&pos| {
+        let before = decl[..pos].chars().next_back();
+        let after = decl[pos + name.len()..].trim_start();
+        !before.is_some_and(is_ident_char) && after.starts_with('(')
+    })
+}
+
+pub(crate) fn is_c_ident(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{autogen_block, TestDir, LABEL};
+    use crate::{SyncError, Syncer};
+
+    #[test]
+    fn test_jump_table() {
+        let dir = TestDir::new("jump_table");
+        let from = dir.write(
+            "rom.h",
+            "/* header-sync:rom start */\n#define ROM_MAX 4\nint rom_add(int a, int b);\n\
+             extern void rom_init(struct rom_cfg *cfg, void (*cb)(u32));\n\
+             char *rom_name(void);\nstatic inline int rom_max(void) { return ROM_MAX; }\n\
+             /* header-sync:rom end */\n",
+        );
+        let to: Vec<String> = ["rom_api.h", "rom_api.c", "rom_api.ld"]
+            .iter()
+            .map(|name| {
+                let to = dir.write(name, autogen_block("rom", ""));
+                to.to_string_lossy().to_string()
+            })
+            .collect();
+
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            to.iter().map(|s| s.as_str()).collect(),
+            LABEL,
+        );
+        syncer.set_jump_tables(vec!["rom=rom_api@0x10000"]);
+        syncer.run().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&to[0]).unwrap(),
+            autogen_block(
+                "rom",
+                "#define ROM_API_ADDR 0x00010000\n#define ROM_API_ROM_ADD_IDX 0\n\
+                 #define ROM_API_ROM_INIT_IDX 1\n#define ROM_API_ROM_NAME_IDX 2\n\
+                 #define ROM_API_COUNT 3\n\ntypedef struct rom_api {\n\
+                 \x20   int (*rom_add)(int a, int b);\n\
+                 \x20   void (*rom_init)(struct rom_cfg *cfg, void (*cb)(u32));\n\
+                 \x20   char *(*rom_name)(void);\n} rom_api_t;\n\n\
+                 #define ROM_API_TABLE ((const rom_api_t *)ROM_API_ADDR)\n"
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(&to[1]).unwrap(),
+            autogen_block(
+                "rom",
+                "__attribute__((section(\".rom_api\"), used)) const rom_api_t rom_api = {\n\
+                 \x20   .rom_add = rom_add,\n    .rom_init = rom_init,\n    .rom_name = rom_name,\n\
+                 };\n"
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(&to[2]).unwrap(),
+            autogen_block(
+                "rom",
+                ".rom_api 0x00010000 :\n{\n    KEEP(*(.rom_api))\n}\n"
+            )
+        );
+
+        syncer.set_jump_tables(vec!["rom=rom_api@rom"]);
+        assert!(matches!(syncer.run(), Err(SyncError::JumpTable { .. })));
+    }
+
+    #[test]
+    fn test_jump_table_indices() {
+        let dir = TestDir::new("jump_table_indices");
+        let region = |code: &str| {
+            format!(
+                "/* header-sync:rom start */\n{}/* header-sync:rom end */\n",
+                code
+            )
+        };
+        let from = dir.write(
+            "rom.h",
+            region("int rom_add(int a, int b);\nvoid rom_init(void);\n"),
+        );
+        let to = dir.write("rom_api.h", autogen_block("rom", ""));
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_jump_tables(vec!["rom=rom_api@0x10000"]);
+        syncer.run().unwrap();
+
+        // a function inserted in the middle goes to the end of the table
+        fs::write(
+            &from,
+            region(
+                "int rom_add(int a, int b);\nint rom_sub(int a, int b);\nvoid rom_init(void);\n",
+            ),
+        )
+        .unwrap();
+        syncer.run().unwrap();
+        let res = fs::read_to_string(&to).unwrap();
+        assert!(res.contains(
+            "#define ROM_API_ROM_ADD_IDX 0\n#define ROM_API_ROM_INIT_IDX 1\n\
+             #define ROM_API_ROM_SUB_IDX 2\n#define ROM_API_COUNT 3\n"
+        ));
+        assert!(res.contains(
+            "    int (*rom_add)(int a, int b);\n    void (*rom_init)(void);\n\
+             \x20   int (*rom_sub)(int a, int b);\n"
+        ));
+
+        // a function can't be removed from the table
+        fs::write(
+            &from,
+            region("int rom_add(int a, int b);\nvoid rom_init(void);\n"),
+        )
+        .unwrap();
+        match syncer.run() {
+            Err(SyncError::JumpTable { message, .. }) => {
+                assert!(message.starts_with("ROM_SUB with index 2 is gone from rom_api"));
+            }
+            res => panic!("{:?}", res.map(|_| ())),
+        }
+        assert_eq!(fs::read_to_string(&to).unwrap(), res);
+    }
+}
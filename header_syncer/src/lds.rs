@@ -0,0 +1,558 @@
+use std::ops::Range;
+
+use crate::jump::is_c_ident;
+
+/// How a symbol was defined in the link script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LdSymbolKind {
+    /// `sym = expr;`
+    Assign,
+    /// `PROVIDE(sym = expr);`
+    Provide,
+    /// `PROVIDE_HIDDEN(sym = expr);`
+    ProvideHidden,
+    /// `HIDDEN(sym = expr);`
+    Hidden,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdSymbol {
+    pub name: String,
+    pub kind: LdSymbolKind,
+    /// Address of the symbol, `None` if it depends on the location counter
+    pub value: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdMemoryRegion {
+    pub name: String,
+    pub origin: Option<u64>,
+    pub length: Option<u64>,
+}
+
+/// Symbols and memory regions found in a GNU ld script
+#[derive(Debug, Default)]
+pub struct LdScript {
+    pub memory: Vec<LdMemoryRegion>,
+    pub symbols: Vec<LdSymbol>,
+    /// Byte offsets of the definitions of `memory` and `symbols`
+    memory_offsets: Vec<usize>,
+    symbol_offsets: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(u64),
+    Punct(&'static str),
+}
+
+const PUNCTS: [&str; 29] = [
+    "<<=", ">>=", "<<", ">>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "&=",
+    "|=", "=", "+", "-", "*", "/", "%", "&", "|", "~", "!", "<", ">", "?",
+];
+
+impl LdScript {
+    pub fn parse(text: &str) -> LdScript {
+        let (tokens, offsets) = tokenize(text);
+        let mut script = LdScript::default();
+        let mut i = 0;
+
+        while i < tokens.len() {
+            match &tokens[i] {
+                Token::Ident(name) if name == "MEMORY" => {
+                    i = script.parse_memory(&tokens, &offsets, i + 1);
+                    continue;
+                }
+                Token::Ident(name)
+                    if matches!(name.as_str(), "PROVIDE" | "PROVIDE_HIDDEN" | "HIDDEN") =>
+                {
+                    let kind = match name.as_str() {
+                        "PROVIDE" => LdSymbolKind::Provide,
+                        "PROVIDE_HIDDEN" => LdSymbolKind::ProvideHidden,
+                        _ => LdSymbolKind::Hidden,
+                    };
+                    if let (
+                        Some(Token::Punct("(")),
+                        Some(Token::Ident(sym)),
+                        Some(Token::Punct("=")),
+                    ) = (tokens.get(i + 1), tokens.get(i + 2), tokens.get(i + 3))
+                    {
+                        let end = find_expr_end(&tokens, i + 4, ")");
+                        let value = script.eval(&tokens[i + 4..end]);
+                        script.define(sym, kind, value, offsets[i]);
+                        i = end;
+                        continue;
+                    }
+                }
+                Token::Ident(sym) if sym != "." && is_statement_start(&tokens, i) => {
+                    if let Some(Token::Punct(op)) = tokens.get(i + 1) {
+                        if op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=") {
+                            let end = find_expr_end(&tokens, i + 2, ";");
+                            let value = if *op == "=" {
+                                script.eval(&tokens[i + 2..end])
+                            } else {
+                                None
+                            };
+                            script.define(sym, LdSymbolKind::Assign, value, offsets[i]);
+                            i = end;
+                            continue;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        script
+    }
+
+    /// Parses the whole script and returns the memory regions and symbols defined
+    /// in each of the byte ranges, their values may use definitions outside the ranges
+    pub fn parse_ranges(text: &str, ranges: &[Range<usize>]) -> Vec<LdScript> {
+        let script = LdScript::parse(text);
+
+        ranges
+            .iter()
+            .map(|range| {
+                let mut part = LdScript::default();
+                for (region, offset) in script.memory.iter().zip(&script.memory_offsets) {
+                    if range.contains(offset) {
+                        part.memory.push(region.clone());
+                        part.memory_offsets.push(*offset);
+                    }
+                }
+                for (sym, offset) in script.symbols.iter().zip(&script.symbol_offsets) {
+                    if range.contains(offset) {
+                        part.symbols.push(sym.clone());
+                        part.symbol_offsets.push(*offset);
+                    }
+                }
+                part
+            })
+            .collect()
+    }
+
+    /// Generates the `#define`s and `extern` declarations for the script
+    pub fn to_c_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for region in &self.memory {
+            let name = c_macro_name(&region.name);
+            if let Some(origin) = region.origin {
+                lines.push(format!("#define {}_ORIGIN 0x{:08x}", name, origin));
+            }
+            if let Some(length) = region.length {
+                lines.push(format!("#define {}_LENGTH 0x{:08x}", name, length));
+            }
+        }
+
+        for sym in &self.symbols {
+            if let Some(value) = sym.value {
+                lines.push(format!(
+                    "#define {}_ADDR 0x{:08x}",
+                    c_macro_name(&sym.name),
+                    value
+                ));
+            }
+        }
+
+        // names like `.text.end` or `$x` can not be declared in C
+        for sym in self.symbols.iter().filter(|sym| is_c_ident(&sym.name)) {
+            lines.push(format!("extern char {}[];", sym.name));
+        }
+
+        lines
+    }
+
+    fn define(&mut self, name: &str, kind: LdSymbolKind, value: Option<u64>, offset: usize) {
+        match self.symbols.iter().position(|s| s.name == name) {
+            // PROVIDE only takes effect if the symbol is not defined elsewhere
+            Some(_) if kind != LdSymbolKind::Assign && kind != LdSymbolKind::Hidden => {}
+            Some(i) => {
+                self.symbols[i].kind = kind;
+                self.symbols[i].value = value;
+                self.symbol_offsets[i] = offset;
+            }
+            None => {
+                self.symbols.push(LdSymbol {
+                    name: name.to_string(),
+                    kind,
+                    value,
+                });
+                self.symbol_offsets.push(offset);
+            }
+        }
+    }
+
+    /// Parses the body of `MEMORY { ... }`, returns the index after the closing brace
+    fn parse_memory(&mut self, tokens: &[Token], offsets: &[usize], mut i: usize) -> usize {
+        if tokens.get(i) != Some(&Token::Punct("{")) {
+            return i;
+        }
+        i += 1;
+
+        while i < tokens.len() && tokens[i] != Token::Punct("}") {
+            let offset = offsets[i];
+            let name = match &tokens[i] {
+                Token::Ident(name) => name.clone(),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            i += 1;
+
+            // skip the optional attributes, e.g. `(rx)`
+            if tokens.get(i) == Some(&Token::Punct("(")) {
+                i = find_expr_end(tokens, i + 1, ")") + 1;
+            }
+            if tokens.get(i) == Some(&Token::Punct(":")) {
+                i += 1;
+            }
+
+            let mut region = LdMemoryRegion {
+                name,
+                origin: None,
+                length: None,
+            };
+            for _ in 0..2 {
+                let key = match tokens.get(i) {
+                    Some(Token::Ident(key)) => key.to_ascii_lowercase(),
+                    _ => break,
+                };
+                if tokens.get(i + 1) != Some(&Token::Punct("=")) {
+                    break;
+                }
+                let end = find_memory_expr_end(tokens, i + 2);
+                let value = self.eval(&tokens[i + 2..end]);
+                match key.as_str() {
+                    "origin" | "org" | "o" => region.origin = value,
+                    "length" | "len" | "l" => region.length = value,
+                    _ => {}
+                }
+                i = end;
+                if tokens.get(i) == Some(&Token::Punct(",")) {
+                    i += 1;
+                }
+            }
+            self.memory.push(region);
+            self.memory_offsets.push(offset);
+        }
+
+        i + 1
+    }
+
+    fn eval(&self, tokens: &[Token]) -> Option<u64> {
+        let mut pos = 0;
+        let value = self.eval_binary(tokens, &mut pos, 0)?;
+        if pos == tokens.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn eval_binary(&self, tokens: &[Token], pos: &mut usize, min_prec: u8) -> Option<u64> {
+        let mut lhs = self.eval_unary(tokens, pos)?;
+
+        while let Some(Token::Punct(op)) = tokens.get(*pos) {
+            let prec = match *op {
+                "*" | "/" | "%" => 5,
+                "+" | "-" => 4,
+                "<<" | ">>" => 3,
+                "&" => 2,
+                "|" => 1,
+                _ => break,
+            };
+            if prec < min_prec {
+                break;
+            }
+            *pos += 1;
+            let rhs = self.eval_binary(tokens, pos, prec + 1)?;
+            lhs = match *op {
+                "*" => lhs.wrapping_mul(rhs),
+                "/" => lhs.checked_div(rhs)?,
+                "%" => lhs.checked_rem(rhs)?,
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "<<" => lhs.checked_shl(rhs as u32)?,
+                ">>" => lhs.checked_shr(rhs as u32)?,
+                "&" => lhs & rhs,
+                _ => lhs | rhs,
+            };
+        }
+
+        Some(lhs)
+    }
+
+    fn eval_unary(&self, tokens: &[Token], pos: &mut usize) -> Option<u64> {
+        let token = tokens.get(*pos)?;
+        *pos += 1;
+
+        match token {
+            Token::Number(n) => Some(*n),
+            Token::Punct("-") => Some(self.eval_unary(tokens, pos)?.wrapping_neg()),
+            Token::Punct("~") => Some(!self.eval_unary(tokens, pos)?),
+            Token::Punct("(") => {
+                let value = self.eval_binary(tokens, pos, 0)?;
+                match tokens.get(*pos) {
+                    Some(Token::Punct(")")) => {
+                        *pos += 1;
+                        Some(value)
+                    }
+                    _ => None,
+                }
+            }
+            Token::Ident(name) if tokens.get(*pos) == Some(&Token::Punct("(")) => {
+                *pos += 1;
+                let end = find_expr_end(tokens, *pos, ")");
+                let args: Vec<&[Token]> = tokens[*pos..end]
+                    .split(|t| *t == Token::Punct(","))
+                    .collect();
+                *pos = end + 1;
+                self.eval_function(name, &args)
+            }
+            Token::Ident(name) => self
+                .symbols
+                .iter()
+                .find(|s| &s.name == name)
+                .and_then(|s| s.value),
+            _ => None,
+        }
+    }
+
+    fn eval_function(&self, name: &str, args: &[&[Token]]) -> Option<u64> {
+        let region = |args: &[&[Token]]| match args {
+            [[Token::Ident(region)]] => self.memory.iter().find(|m| &m.name == region),
+            _ => None,
+        };
+
+        match (name, args.len()) {
+            ("ORIGIN", 1) => region(args)?.origin,
+            ("LENGTH", 1) => region(args)?.length,
+            ("ABSOLUTE", 1) => self.eval(args[0]),
+            ("ALIGN", 2) => {
+                let value = self.eval(args[0])?;
+                let align = self.eval(args[1])?;
+                if align == 0 {
+                    return None;
+                }
+                Some(value.div_ceil(align) * align)
+            }
+            ("MIN", 2) => Some(self.eval(args[0])?.min(self.eval(args[1])?)),
+            ("MAX", 2) => Some(self.eval(args[0])?.max(self.eval(args[1])?)),
+            _ => None,
+        }
+    }
+}
+
+/// A symbol assignment must be a statement of its own
+fn is_statement_start(tokens: &[Token], i: usize) -> bool {
+    i == 0
+        || matches!(
+            tokens[i - 1],
+            Token::Punct(";") | Token::Punct("{") | Token::Punct("}") | Token::Punct(")")
+        )
+}
+
+/// Returns the index of `term` at nesting depth zero, or the end of the current block
+fn find_expr_end(tokens: &[Token], start: usize, term: &str) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Punct(p) if *p == term && depth == 0 => return i,
+            Token::Punct("(") | Token::Punct("{") => depth += 1,
+            Token::Punct(")") | Token::Punct("}") => {
+                if depth == 0 {
+                    return i;
+                }
+                depth -= 1;
+            }
+            Token::Punct(";") if depth == 0 => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    i
+}
+
+/// Expressions in `MEMORY` may be followed by the next region without a separator
+fn find_memory_expr_end(tokens: &[Token], start: usize) -> usize {
+    let mut depth = 0;
+    let mut prev_is_operand = false;
+
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        let is_operand = match token {
+            Token::Punct("(") => {
+                depth += 1;
+                false
+            }
+            Token::Punct(")") if depth > 0 => {
+                depth -= 1;
+                true
+            }
+            Token::Punct(",") | Token::Punct(")") | Token::Punct("}") if depth == 0 => return i,
+            Token::Ident(_) | Token::Number(_) => {
+                if prev_is_operand && depth == 0 {
+                    return i;
+                }
+                true
+            }
+            _ => false,
+        };
+        prev_is_operand = is_operand;
+    }
+
+    tokens.len()
+}
+
+/// Returns the tokens and their byte offsets in the text
+fn tokenize(text: &str) -> (Vec<Token>, Vec<usize>) {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut offsets = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if text[i..].starts_with("/*") {
+            i = text[i + 2..]
+                .find("*/")
+                .map_or(bytes.len(), |p| i + 2 + p + 2);
+        } else if text[i..].starts_with("//") || c == b'#' {
+            i = text[i..].find('\n').map_or(bytes.len(), |p| i + p);
+        } else if c == b'"' {
+            let end = text[i + 1..]
+                .find('"')
+                .map_or(bytes.len(), |p| i + 1 + p + 1);
+            tokens.push(Token::Ident(text[i + 1..end.saturating_sub(1)].to_string()));
+            i = end;
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            match parse_number(&text[start..i]) {
+                Some(n) => tokens.push(Token::Number(n)),
+                None => tokens.push(Token::Ident(text[start..i].to_string())),
+            }
+        } else if c.is_ascii_alphabetic() || matches!(c, b'_' | b'.' | b'$') {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'_' | b'.' | b'$'))
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(text[start..i].to_string()));
+        } else if let Some(p) = PUNCTS.iter().find(|p| text[i..].starts_with(**p)) {
+            tokens.push(Token::Punct(p));
+            i += p.len();
+        } else {
+            let p = match c {
+                b'(' => "(",
+                b')' => ")",
+                b'{' => "{",
+                b'}' => "}",
+                b';' => ";",
+                b',' => ",",
+                b':' => ":",
+                _ => "",
+            };
+            if !p.is_empty() {
+                tokens.push(Token::Punct(p));
+            }
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+        }
+        offsets.resize(tokens.len(), start);
+    }
+
+    (tokens, offsets)
+}
+
+/// Parses numbers like `0x1000`, `64K`, `0x10K`, `1M` or `0755`
+fn parse_number(s: &str) -> Option<u64> {
+    let (digits, mult) = match s.chars().last()? {
+        'K' | 'k' => (&s[..s.len() - 1], 1024),
+        'M' | 'm' => (&s[..s.len() - 1], 1024 * 1024),
+        _ => (s, 1),
+    };
+
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    value.checked_mul(mult)
+}
+
+pub(crate) fn c_macro_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::example;
+    use crate::LdScript;
+
+    #[test]
+    fn test_parse_link_script() {
+        let text = fs::read_to_string(example("rom.ld")).unwrap();
+        let lines = LdScript::parse(&text).to_c_lines();
+
+        assert!(lines.contains(&"#define ROM_LENGTH 0x00010000".to_string()));
+        assert!(lines.contains(&"#define RAM_ORIGIN 0x20000000".to_string()));
+        assert!(lines.contains(&"#define _ESTACK_ADDR 0x20004000".to_string()));
+        assert!(lines.contains(&"#define ROM_MEMCPY_ADDR 0x00001334".to_string()));
+        assert!(lines.contains(&"extern char __bss_end__[];".to_string()));
+        assert!(!lines.iter().any(|l| l.contains("_ETEXT_ADDR")));
+
+        let script = LdScript::parse(
+            "MEMORY { SRAM : ORIGIN = 0x10000, LENGTH = 0x10K }\nä = 1; µ_end = 2;",
+        );
+        assert_eq!(script.memory[0].length, Some(0x4000));
+
+        let text = "MEMORY { rom : ORIGIN = 0x1000, LENGTH = 4K }\n_base = 0x100;\n\
+                    /* start */\nrom_end = ORIGIN(rom) + LENGTH(rom);\nrom_api = _base + 4;\n\
+                    .text.end = .;\n$x = 1;\n/* end */\n_top = 0;\n";
+        let start = text.find("/* start */").unwrap();
+        let end = text.find("/* end */").unwrap();
+        let parts = LdScript::parse_ranges(text, &[start..end, end..text.len()]);
+        assert!(parts[0].memory.is_empty());
+        let names: Vec<&str> = parts[0].symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["rom_end", "rom_api", ".text.end", "$x"]);
+        assert_eq!(
+            parts[0].to_c_lines(),
+            [
+                "#define ROM_END_ADDR 0x00002000",
+                "#define ROM_API_ADDR 0x00000104",
+                "#define _X_ADDR 0x00000001",
+                "extern char rom_end[];",
+                "extern char rom_api[];",
+            ]
+        );
+        assert_eq!(parts[1].symbols[0].name, "_top");
+    }
+}
@@ -1,35 +1,130 @@
-use std::fs::OpenOptions;
-use std::io::BufRead;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::io::Write;
-use std::io::{Seek, SeekFrom};
-use std::{
-    fs::{self, File},
-    io::BufReader,
-};
+use std::ops::Range;
+use std::path::Path;
 
-// use lang_c::driver::{parse, Config};
-use rand::prelude::*;
+use lang_c::driver::{Config, Flavor};
+
+mod compress;
+pub use compress::*;
+
+mod conflict;
+pub use conflict::*;
+
+mod deps;
+pub use deps::*;
+
+mod dwarf;
+
+mod elf;
+pub use elf::*;
+
+mod error;
+pub use error::*;
+
+mod history;
+pub use history::*;
+
+mod job;
+pub use job::*;
+
+mod jump;
+pub use jump::*;
+
+mod lds;
+pub use lds::*;
+
+mod mark;
+pub use mark::*;
+
+mod map;
+pub use map::*;
+
+mod merge;
+pub use merge::*;
+
+mod pattern;
+pub use pattern::*;
+
+mod plan;
+pub use plan::*;
+
+mod preprocess;
+pub use preprocess::*;
+
+mod provide;
+pub use provide::*;
+
+mod stamp;
+pub use stamp::*;
+
+mod symbol;
+pub use symbol::*;
+
+mod text;
+pub use text::*;
+
+mod tidy;
+pub use tidy::*;
+
+mod toolchain;
+pub use toolchain::*;
+
+mod watch;
+pub use watch::*;
 
 pub enum FromFileType {
     Header,
     GnuLinkScript,
+    /// ELF executable, object file or `ar` archive of object files, see [`ElfFile`]
+    Elf,
+    /// GNU ld map file, see [`MapFile`]
+    GnuMap,
+}
+
+/// How the sync region of a header is read
+#[derive(PartialEq)]
+pub enum ParseMode {
+    /// Copy the region line by line
+    Text,
+    /// Run the header through the preprocessor and lang-c, and sync each declaration
+    Ast,
 }
 
 pub struct Syncer {
     from: Vec<String>,
     to: Vec<String>,
     type_of_from: FromFileType,
+    parse_mode: ParseMode,
     label: String,
     class_name: String,
     ignore_symbols: Vec<String>,
+    unmatched_ignore_symbols: Vec<String>,
+    keep_symbols: Vec<String>,
     mark_symbols: Vec<String>,
+    jump_tables: Vec<String>,
     compress: bool,
+    tidy: bool,
+    elf_output: ElfOutput,
+    map_output: MapOutput,
+    resolve_types: bool,
+    unresolved_types: Vec<String>,
+    addresses: Option<String>,
+    missing_addresses: Vec<String>,
+    warnings: Vec<String>,
+    history: usize,
+    incremental: bool,
+    stamp: bool,
+    preprocessor: Option<Preprocessor>,
+    toolchain: Option<Toolchain>,
+    encoding: Option<&'static encoding_rs::Encoding>,
 }
 
 #[derive(Debug)]
 enum CheckLabelRsp {
-    Start,
-    End,
+    Start(String),
+    End(String),
     None,
 }
 
@@ -39,11 +134,29 @@ impl Syncer {
             from: from.iter().map(|s| s.to_string()).collect(),
             to: to.iter().map(|s| s.to_string()).collect(),
             type_of_from: FromFileType::Header,
+            parse_mode: ParseMode::Text,
             label: label.to_string(),
             class_name: String::new(),
             ignore_symbols: Vec::new(),
+            unmatched_ignore_symbols: Vec::new(),
+            keep_symbols: Vec::new(),
             mark_symbols: Vec::new(),
-            compress: true,
+            jump_tables: Vec::new(),
+            compress: false,
+            tidy: false,
+            elf_output: ElfOutput::Extern,
+            map_output: MapOutput::Defines,
+            resolve_types: false,
+            unresolved_types: Vec::new(),
+            addresses: None,
+            missing_addresses: Vec::new(),
+            warnings: Vec::new(),
+            history: 0,
+            incremental: false,
+            stamp: false,
+            preprocessor: None,
+            toolchain: None,
+            encoding: None,
         }
     }
 
@@ -51,192 +164,1247 @@ impl Syncer {
         self.type_of_from = type_of_from;
     }
 
+    pub fn set_parse_mode(&mut self, parse_mode: ParseMode) {
+        self.parse_mode = parse_mode;
+    }
+
     pub fn set_class_name(&mut self, name: &str) {
         self.class_name = name.to_string();
     }
 
+    /// Sets the symbols to leave out, see [`SymbolPattern`] for the rules
     pub fn set_ignore_symbols(&mut self, ignore: Vec<&str>) {
-        self.ignore_symbols = ignore.iter().map(|s| s.to_string()).collect();
+        self.ignore_symbols = ignore
+            .iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
     }
 
+    /// Returns the ignore rules that matched no symbol in the last run
+    pub fn unmatched_ignore_symbols(&self) -> &[String] {
+        &self.unmatched_ignore_symbols
+    }
+
+    /// Sets the symbols to sync, all by default, see [`SymbolPattern`] for the rules.
+    /// The ignore rules still apply to them.
+    pub fn set_keep_symbols(&mut self, keep: Vec<&str>) {
+        self.keep_symbols = keep
+            .iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+    }
+
+    /// Sets the marks added to header symbols, see [`MarkRule`] for the rules
     pub fn set_mark_symbols(&mut self, mark: Vec<&str>) {
-        self.mark_symbols = mark.iter().map(|s| s.to_string()).collect();
+        self.mark_symbols = mark
+            .iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
     }
 
+    /// Writes the function prototypes of the regions named by the rules as jump tables,
+    /// see [`JumpTableRule`]. Each To file gets the part of the table for its extension,
+    /// see [`JumpTableOutput`]; the other declarations of these regions are not synced.
+    /// The indices already written in the header To files are kept, new functions are appended.
+    pub fn set_jump_tables(&mut self, rules: Vec<&str>) {
+        self.jump_tables = rules
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+    }
+
+    /// Writes each declaration in its [`compress_declaration`] form, without blank lines,
+    /// and drops repeated declarations such as duplicate forward declarations
     pub fn set_compress(&mut self, compress: bool) {
         self.compress = compress;
     }
 
-    pub fn run(&mut self) {
-        let mut rng = rand::thread_rng();
-        let tmp: u32 = rng.gen();
-        let tmp_name = format!("header_syncer_{}", tmp);
-        // println!("{}", tmp_name);
-        let tmp_file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(&tmp_name)
-            .unwrap();
+    /// Tidies each autogen block written, see [`tidy_block`]
+    pub fn set_tidy(&mut self, tidy: bool) {
+        self.tidy = tidy;
+    }
+
+    /// Sets what is written for the symbols of ELF From files
+    pub fn set_elf_output(&mut self, output: ElfOutput) {
+        self.elf_output = output;
+    }
+
+    /// Sets what is written for GNU ld map From files
+    pub fn set_map_output(&mut self, output: MapOutput) {
+        self.map_output = output;
+    }
+
+    /// Writes the declarations of the types the synced symbols use in front of them,
+    /// taken from any From header, see [`type_closure`]
+    pub fn set_resolve_types(&mut self, resolve: bool) {
+        self.resolve_types = resolve;
+    }
+
+    /// Returns the types used by synced symbols that no From header defines,
+    /// found in the last run with [`Syncer::set_resolve_types`]
+    pub fn unresolved_types(&self) -> &[String] {
+        &self.unresolved_types
+    }
+
+    /// Writes `PROVIDE(<symbol> = <address>);` lines for the functions and variables
+    /// into the `.ld` and `.lds` To files instead of the declarations,
+    /// with the addresses read from an ELF, map or CSV file, see [`SymbolAddresses`]
+    pub fn set_provide_addresses(&mut self, path: &str) {
+        self.addresses = Some(path.to_string());
+    }
 
+    /// Returns the symbols the address source has no address for, found by the last sync
+    pub fn missing_addresses(&self) -> &[String] {
+        &self.missing_addresses
+    }
+
+    /// Returns the warnings of the last sync or tidy, e.g. rules that matched nothing
+    /// and the entries of [`Syncer::unresolved_types`] and [`Syncer::missing_addresses`]
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Keeps the last `keep` replaced autogen blocks of each To file in [`HISTORY_DIR`],
+    /// 0 keeps none
+    pub fn set_history(&mut self, keep: usize) {
+        self.history = keep;
+    }
+
+    /// Merges the symbols into the existing autogen block instead of rewriting it,
+    /// see [`merge_block`]
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.incremental = incremental;
+    }
+
+    /// Writes a [`Stamp`] line at the start of each autogen block
+    pub fn set_stamp(&mut self, stamp: bool) {
+        self.stamp = stamp;
+    }
+
+    /// Evaluates the `#if` blocks of the From headers, see [`Preprocessor`]
+    pub fn set_preprocessor(&mut self, preprocessor: Preprocessor) {
+        self.preprocessor = Some(preprocessor);
+    }
+
+    /// Sets the C preprocessor of the `ast` parse mode, `gcc -E` from `PATH` by default
+    pub fn set_toolchain(&mut self, toolchain: Toolchain) {
+        self.toolchain = Some(toolchain);
+    }
+
+    /// Sets the encoding of the From and To files that are not UTF-8, e.g. `gbk`.
+    /// Each To file is written back in its own encoding and line endings, see [`TextFormat`].
+    /// In the `ast` parse mode GCC is told the encoding, clang only reads UTF-8 headers.
+    pub fn set_encoding(&mut self, label: &str) -> Result<(), String> {
+        self.encoding = Some(find_encoding(label)?);
+        Ok(())
+    }
+
+    /// Returns the From files and the address source
+    pub fn sources(&self) -> Vec<String> {
+        self.from.iter().chain(&self.addresses).cloned().collect()
+    }
+
+    /// Returns each To file with the name of each region of the From files,
+    /// `""` for the unnamed one, i.e. the autogen blocks a sync may write
+    pub fn targets(&self) -> Result<Vec<(String, String)>, SyncError> {
+        let mut regions: Vec<String> = Vec::new();
         for f in &self.from {
-            let file = File::open(f);
-            match file {
-                Ok(file) => {
-                    if let Some((start, end)) =
-                        self.get_label_position_in_file(&file, "start", "end")
-                    {
-                        let mut line = String::new();
-                        let mut reader = BufReader::new(&file);
-                        reader.seek(SeekFrom::Start(start as u64)).unwrap();
-
-                        // let name = f.split("\\").last().unwrap();
-                        // writeln!(&tmp_file, "// {}", name).unwrap();
-                        loop {
-                            reader.read_line(&mut line).unwrap();
-                            if reader.stream_position().unwrap() >= end as u64 {
-                                break;
-                            }
-                            writeln!(&tmp_file, "{}", line.trim_end()).unwrap();
-                            line.clear();
-                        }
+            let names = match self.type_of_from {
+                FromFileType::Header | FromFileType::GnuLinkScript => {
+                    let (text, _) = read_text(f, self.encoding)?;
+                    let names: Vec<String> = self
+                        .get_regions(&text, "start", "end")
+                        .into_iter()
+                        .map(|r| r.name)
+                        .collect();
+                    match self.type_of_from {
+                        // an unlabeled link script is synced as a whole
+                        FromFileType::GnuLinkScript if names.is_empty() => vec![String::new()],
+                        _ => names,
                     }
                 }
-                Err(e) => {
-                    panic!("{} open failed, {}", &f, e);
+                FromFileType::Elf | FromFileType::GnuMap => vec![String::new()],
+            };
+            for name in names {
+                if !regions.contains(&name) {
+                    regions.push(name);
                 }
             }
         }
-        drop(tmp_file);
+
+        Ok(self
+            .to
+            .iter()
+            .flat_map(|to| regions.iter().map(|r| (to.clone(), r.clone())))
+            .collect())
+    }
+
+    /// Syncs the From files into every To file that has an autogen block,
+    /// returns the changes written
+    pub fn run(&mut self) -> Result<Vec<TargetChange>, SyncError> {
+        let mut written = Vec::new();
+
+        for change in self.plan()? {
+            if !change.is_changed() {
+                continue;
+            }
+
+            if self.history > 0 {
+                self.save_history(&change.path, &change.old, &change.new)?;
+            }
+            self.write_target(&change.path, &change.new, &change.format)?;
+            written.push(change);
+        }
+
+        Ok(written)
+    }
+
+    /// Puts a block saved by an earlier run back into the autogen block named `region`
+    /// of a To file, the newest generation is used if `generation` is `None`.
+    ///
+    /// The block being replaced is saved to the history as well, so a restore can be undone.
+    /// Returns the restored generation.
+    pub fn restore(
+        &self,
+        target: &str,
+        region: &str,
+        generation: Option<&str>,
+    ) -> Result<String, SyncError> {
+        let history = History::new(target, region).map_err(|e| SyncError::io(target, e))?;
+        let generation = match generation {
+            Some(generation) => generation.to_string(),
+            None => history
+                .generations()
+                .pop()
+                .ok_or_else(|| SyncError::NoHistory {
+                    path: target.to_string(),
+                    region: region.to_string(),
+                })?,
+        };
+        let block = history
+            .load(&generation)
+            .map_err(|e| SyncError::io(history.path(&generation).to_string_lossy(), e))?;
+        let (text, format) = read_text(target, self.encoding)?;
+        let regions = self.get_regions(&text, "autogen start", "autogen end");
+        let region =
+            regions
+                .iter()
+                .find(|r| r.name == region)
+                .ok_or_else(|| SyncError::NoBlock {
+                    path: target.to_string(),
+                    region: region.to_string(),
+                })?;
+
+        let block: Vec<String> = block.lines().map(|l| l.to_string()).collect();
+        let new = self.replace_blocks(&text, &[(region, block)]);
+        if new != text {
+            self.save_history(target, &text, &new)?;
+            self.write_target(target, &new, &format)?;
+        }
+
+        Ok(generation)
+    }
+
+    /// Saves the autogen blocks of `old` that differ in `new` to the history of the To file
+    fn save_history(&self, target: &str, old: &str, new: &str) -> Result<(), SyncError> {
+        let new_regions = self.get_regions(new, "autogen start", "autogen end");
+
+        for region in self.get_regions(old, "autogen start", "autogen end") {
+            let block = block_lines(old, &region);
+            let changed = new_regions
+                .iter()
+                .find(|r| r.name == region.name)
+                .is_none_or(|r| block_lines(new, r) != block);
+            if !changed {
+                continue;
+            }
+
+            let history =
+                History::new(target, &region.name).map_err(|e| SyncError::io(target, e))?;
+            let mut block = block.join("\n");
+            if !block.is_empty() {
+                block.push('\n');
+            }
+            let mut res = history.save(&block).map(|_| ());
+            if self.history > 0 {
+                res = res.and_then(|_| history.prune(self.history));
+            }
+            res.map_err(|e| SyncError::io(history.dir().to_string_lossy(), e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a To file through a temporary file in the same directory that is renamed over it,
+    /// so the To file is either the old or the new one whenever the write stops
+    fn write_target(&self, f: &str, content: &str, format: &TextFormat) -> Result<(), SyncError> {
+        let content = format
+            .encode(content)
+            .map_err(|message| SyncError::Encoding {
+                path: f.to_string(),
+                message,
+            })?;
+        let path = Path::new(f);
+        let name = path
+            .file_name()
+            .map_or("target".into(), |name| name.to_string_lossy());
+        let tmp = path.with_file_name(format!(".{}.{:08x}.tmp", name, rand::random::<u32>()));
+
+        let res = File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(&content)?;
+                if let Ok(metadata) = fs::metadata(path) {
+                    file.set_permissions(metadata.permissions())?;
+                }
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, path));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+
+        res.map_err(|e| SyncError::io(f, e))
+    }
+
+    /// Returns the change `run` would make to each To file, without writing anything.
+    ///
+    /// Autogen blocks whose name matches no region of the From files are left as they are.
+    pub fn plan(&mut self) -> Result<Vec<TargetChange>, SyncError> {
+        let blocks = self.generate_blocks()?;
+        let mut changes = Vec::new();
+        let mut warnings = Vec::new();
 
         for f in &self.to {
-            let file = File::open(f);
-
-            match file {
-                Ok(file) => {
-                    let start_string = "autogen start";
-                    let end_string = "autogen end";
-
-                    // println!(
-                    //     "in to file {:?}",
-                    //     self.get_label_position_in_file(&file, start_string, end_string)
-                    // );
-
-                    if let Some((start, end)) =
-                        self.get_label_position_in_file(&file, start_string, end_string)
-                    {
-                        let new_file = File::create(f.to_string() + ".new").unwrap();
-                        let mut line = String::new();
-                        let mut reader = BufReader::new(&file);
-
-                        reader.rewind().unwrap();
-                        loop {
-                            reader.read_line(&mut line).unwrap();
-                            if reader.stream_position().unwrap() >= start as u64 {
-                                line.clear();
-                                break;
-                            }
-                            // println!("1 line {}", line.trim_end());
-                            writeln!(&new_file, "{}", line.trim_end()).unwrap();
-                            line.clear();
+            let (text, format) = read_text(f, self.encoding)?;
+
+            let regions = self.get_regions(&text, "autogen start", "autogen end");
+            if regions.is_empty() {
+                continue;
+            }
+
+            let mut replaced = Vec::new();
+            let mut changed_regions = Vec::new();
+            for region in &regions {
+                let block = match blocks.iter().find(|b| b.name == region.name) {
+                    Some(block) => block,
+                    None => continue,
+                };
+
+                let output = JumpTableOutput::for_path(f);
+                // GNU ld only has `/* */` comments
+                let stamp_prefix = match output {
+                    JumpTableOutput::LinkScript => format!("/* {}:", label_words(&self.label)),
+                    _ => format!("// {}:", label_words(&self.label)),
+                };
+                let mut old = block_lines(&text, region);
+                let old_stamp = match old.first() {
+                    Some(line) if Stamp::is_stamp(&stamp_prefix, line) => Some(old.remove(0)),
+                    _ => None,
+                };
+
+                let mut lines = block.lines.clone();
+                if let Some(table) = &block.jump_table {
+                    lines.extend(table.to_c_lines(output));
+                } else if let Some(provide) = block
+                    .provide
+                    .as_ref()
+                    .filter(|_| output == JumpTableOutput::LinkScript)
+                {
+                    lines = provide.clone();
+                }
+                if output == JumpTableOutput::LinkScript {
+                    for line in &mut lines {
+                        if let Some(comment) = line.strip_prefix("// ") {
+                            *line = format!("/* {} */", comment);
                         }
+                    }
+                }
+                let mut new_block = if self.incremental {
+                    merge_block(&old, &lines, &self.banners(&region.name))
+                } else {
+                    lines
+                };
+                if self.tidy && output != JumpTableOutput::LinkScript {
+                    new_block = self.tidy_lines(f, &region.name, new_block, &mut warnings);
+                }
+                if self.stamp {
+                    let stamp = Stamp {
+                        sources: block.sources.clone(),
+                        input_hash: block.input_hash,
+                    };
+                    new_block.insert(0, stamp.line(&stamp_prefix, old_stamp));
+                }
+                if block_lines(&text, region).join("\n") != new_block.join("\n") {
+                    changed_regions.push(region.name.clone());
+                }
+                replaced.push((region, new_block));
+            }
 
-                        let mut label: Vec<&str> = self.label.split(' ').collect();
-                        let label_last = label.pop().unwrap();
+            let new = self.replace_blocks(&text, &replaced);
+            changes.push(TargetChange {
+                path: f.to_string(),
+                old: text,
+                new,
+                regions: changed_regions,
+                format,
+            });
+        }
+        self.warnings.append(&mut warnings);
 
-                        // write start label
-                        let mut slabel = label.clone();
-                        slabel.push(start_string);
-                        slabel.push(label_last);
-                        writeln!(&new_file, "{}", slabel.join(" ")).unwrap();
+        Ok(changes)
+    }
 
-                        // need to copy
-                        let tmp_file = File::open(&tmp_name).unwrap();
-                        let tmp_reader = BufReader::new(&tmp_file);
+    /// Returns the lines to put between the autogen labels of each region
+    fn generate_blocks(&mut self) -> Result<Vec<Block>, SyncError> {
+        let patterns = |rules: &[String]| {
+            rules
+                .iter()
+                .map(|rule| {
+                    SymbolPattern::new(rule).map_err(|source| SyncError::Pattern {
+                        rule: rule.to_string(),
+                        source,
+                    })
+                })
+                .collect::<Result<Vec<SymbolPattern>, SyncError>>()
+        };
+        let ignore = patterns(&self.ignore_symbols)?;
+        let mut ignore_matched = vec![false; ignore.len()];
+        let keep = patterns(&self.keep_symbols)?;
+        let mut keep_matched = vec![false; keep.len()];
+        let marks = self
+            .mark_symbols
+            .iter()
+            .map(|rule| {
+                MarkRule::new(rule).map_err(|message| SyncError::Mark {
+                    rule: rule.to_string(),
+                    message,
+                })
+            })
+            .collect::<Result<Vec<MarkRule>, SyncError>>()?;
+        let mut marks_matched = vec![false; marks.len()];
+        let jump_rules = self
+            .jump_tables
+            .iter()
+            .map(|rule| {
+                JumpTableRule::new(rule).map_err(|message| SyncError::JumpTable {
+                    rule: rule.to_string(),
+                    message,
+                })
+            })
+            .collect::<Result<Vec<JumpTableRule>, SyncError>>()?;
+        let mut tables: HashMap<String, JumpTable> = jump_rules
+            .iter()
+            .map(|rule| (rule.region().to_string(), rule.table()))
+            .collect();
+        let mut is_ignored = |name: &str| {
+            let mut ignored = false;
+            for (i, pattern) in ignore.iter().enumerate() {
+                if pattern.is_match(name) {
+                    ignore_matched[i] = true;
+                    ignored = true;
+                }
+            }
+            let mut kept = keep.is_empty();
+            for (i, pattern) in keep.iter().enumerate() {
+                if pattern.is_match(name) {
+                    keep_matched[i] = true;
+                    kept = true;
+                }
+            }
+            ignored || !kept
+        };
 
-                        for line in tmp_reader.lines() {
-                            if let Ok(line) = line {
-                                // println!("line {}", line);
-                                writeln!(&new_file, "{}", line).unwrap();
-                            }
+        // declarations of types in the From headers, and the types in each region
+        let mut defs: Vec<Symbol> = Vec::new();
+        let mut region_types: HashMap<String, HashSet<TypeName>> = HashMap::new();
+        let is_synced = |sym: &Symbol| {
+            sym.name.is_empty()
+                || (!ignore.iter().any(|p| p.is_match(&sym.name))
+                    && (keep.is_empty() || keep.iter().any(|p| p.is_match(&sym.name))))
+        };
+        self.unresolved_types.clear();
+        self.missing_addresses.clear();
+        self.warnings.clear();
+        // the `#if` blocks of each From header are evaluated once
+        let mut kept_lines: HashMap<String, Vec<bool>> = HashMap::new();
+        if let (Some(pp), FromFileType::Header) = (&self.preprocessor, &self.type_of_from) {
+            for f in &self.from {
+                let (text, format) = read_text(f, self.encoding)?;
+                let (kept, mut warnings) = self.kept_lines(pp, f, &text, &format)?;
+                self.warnings.append(&mut warnings);
+                kept_lines.insert(f.clone(), kept);
+            }
+        }
+        let kept = |f: &str| kept_lines.get(f).map(Vec::as_slice);
+        if self.resolve_types && matches!(self.type_of_from, FromFileType::Header) {
+            for f in &self.from {
+                let (text, format) = read_text(f, self.encoding)?;
+                let all_lines = 1..text.lines().count() + 1;
+                for mut sym in self.read_symbols(f, &text, &format, all_lines, kept(f))?.0 {
+                    // labels end up in the leading comments of the first symbol of a region
+                    sym.text = sym
+                        .text
+                        .lines()
+                        .filter(|l| {
+                            matches!(self.check_label(l, "start", "end"), CheckLabelRsp::None)
+                        })
+                        .collect::<Vec<&str>>()
+                        .join("\n");
+                    if !defined_types(&sym).is_empty() && is_synced(&sym) {
+                        defs.push(sym);
+                    }
+                }
+                for (name, lines) in self.get_label_lines(&text) {
+                    let types = region_types.entry(name).or_default();
+                    for sym in self.read_symbols(f, &text, &format, lines, kept(f))?.0 {
+                        if is_synced(&sym) {
+                            types.extend(defined_types(&sym));
                         }
+                    }
+                }
+            }
+        }
+        let mut conflicts = ConflictChecker::new();
 
-                        // write end label
-                        label.push(end_string);
-                        label.push(label_last);
-                        writeln!(&new_file, "{}", label.join(" ")).unwrap();
+        let mut blocks: Vec<Block> = Vec::new();
+        // region name and text of each compressed declaration
+        let mut declared: HashSet<(String, String)> = HashSet::new();
+        let mut settings_hash = FNV1A64_INIT;
+        // the settings that change what is written, each ended by a 0 byte
+        for setting in [
+            self.class_name.clone(),
+            self.ignore_symbols.join("\n"),
+            self.keep_symbols.join("\n"),
+            self.mark_symbols.join("\n"),
+            self.jump_tables.join("\n"),
+            format!(
+                "{} {} {} {} {:?} {:?}",
+                self.parse_mode == ParseMode::Ast,
+                self.compress,
+                self.tidy,
+                self.resolve_types,
+                self.elf_output,
+                self.map_output
+            ),
+        ] {
+            settings_hash = fnv1a64(settings_hash, setting.as_bytes());
+            settings_hash = fnv1a64(settings_hash, b"\0");
+        }
+        if let Some(pp) = &self.preprocessor {
+            for option in pp.cpp_options() {
+                settings_hash = fnv1a64(settings_hash, option.as_bytes());
+            }
+        }
+        if let Some(toolchain) = &self.toolchain {
+            settings_hash = fnv1a64(settings_hash, toolchain.command().as_bytes());
+            for option in toolchain.options() {
+                settings_hash = fnv1a64(settings_hash, option.as_bytes());
+            }
+        }
+        let addresses = match &self.addresses {
+            Some(path) => {
+                let data = fs::read(path).map_err(|e| SyncError::io(path, e))?;
+                settings_hash = fnv1a64(settings_hash, &data);
+                Some(
+                    SymbolAddresses::parse(&data).map_err(|message| SyncError::Addresses {
+                        path: path.to_string(),
+                        message,
+                    })?,
+                )
+            }
+            None => None,
+        };
+        for f in &self.from {
+            let (text, format, data) = match self.type_of_from {
+                FromFileType::Elf => (
+                    String::new(),
+                    TextFormat::default(),
+                    fs::read(f).map_err(|e| SyncError::io(f, e))?,
+                ),
+                _ => {
+                    let (text, format) = read_text(f, self.encoding)?;
+                    (text, format, Vec::new())
+                }
+            };
+            let mut sections: Vec<FromSection> = Vec::new();
+
+            match self.type_of_from {
+                FromFileType::Header => {
+                    for (name, lines) in self.get_label_lines(&text) {
+                        let mut input = text
+                            .lines()
+                            .skip(lines.start - 1)
+                            .take(lines.len())
+                            .fold(FNV1A64_INIT, |hash, line| {
+                                fnv1a64(fnv1a64(hash, line.as_bytes()), b"\n")
+                            });
+                        let (symbols, mut skipped) =
+                            self.read_symbols(f, &text, &format, lines, kept(f))?;
+                        self.warnings.append(&mut skipped);
 
-                        reader.seek(SeekFrom::Start(end as u64)).unwrap();
+                        let mut section = Vec::new();
+                        let mut provides = Vec::new();
+                        let mut synced = Vec::new();
+                        let mut prev_end = 0;
+                        let mut written_line = 0;
+                        for sym in symbols {
+                            // a blank line in the source, dropped lines are no gap
+                            let gap = text
+                                .lines()
+                                .take(sym.line.saturating_sub(1))
+                                .skip(prev_end)
+                                .any(|l| l.trim().is_empty());
+                            prev_end = sym.line + sym.text.lines().count() - 1;
+                            if !sym.name.is_empty() && is_ignored(&sym.name) {
+                                continue;
+                            }
+                            // the same declaration from another From file is written once
+                            if conflicts.check(&name, f, &sym)? {
+                                continue;
+                            }
+                            if let Some(addresses) = &addresses {
+                                if SymbolAddresses::is_provided(&sym) {
+                                    if addresses.get(&sym.name).is_none()
+                                        && !self.missing_addresses.contains(&sym.name)
+                                    {
+                                        self.warnings.push(format!(
+                                            "{} has no address in {}",
+                                            sym.name,
+                                            self.addresses.as_deref().unwrap_or_default()
+                                        ));
+                                        self.missing_addresses.push(sym.name.clone());
+                                    }
+                                    provides.push(addresses.provide_line(&sym.name));
+                                }
+                            }
+                            if let Some(table) = tables.get_mut(&name) {
+                                table.push(&sym);
+                                continue;
+                            }
+                            // another declarator of a declaration already written
+                            if sym.line == written_line {
+                                continue;
+                            }
+                            written_line = sym.line;
 
-                        while let Ok(size) = reader.read_line(&mut line) {
-                            if size == 0 {
-                                break;
+                            let mut sym_marks = Vec::new();
+                            for (i, rule) in marks.iter().enumerate() {
+                                if rule.is_match(&sym) {
+                                    marks_matched[i] = true;
+                                    sym_marks.push(rule.mark());
+                                }
                             }
+                            let text = mark_symbol(&sym, &sym_marks);
 
-                            // println!("2 line {}", line.trim_end());
-                            writeln!(&new_file, "{}", line.trim_end()).unwrap();
-                            line.clear();
+                            if self.compress {
+                                let text = compress_declaration(&text);
+                                let repeated = sym.kind != SymbolKind::Other
+                                    && !declared.insert((name.clone(), text.clone()));
+                                if !text.is_empty() && !repeated {
+                                    section.push(text);
+                                }
+                            } else {
+                                if !section.is_empty() && gap {
+                                    section.push(String::new());
+                                }
+                                section.push(text);
+                            }
+                            if self.resolve_types {
+                                synced.push(sym);
+                            }
                         }
 
-                        drop(new_file);
+                        if self.resolve_types {
+                            let synced: Vec<&Symbol> = synced.iter().collect();
+                            let types = region_types.entry(name.clone()).or_default();
+                            let closure = type_closure(&synced, &defs, types);
+                            for (t, user) in closure.unresolved {
+                                self.warnings.push(format!(
+                                    "type {} used by {} is not defined in the From files",
+                                    t, user
+                                ));
+                                if !self.unresolved_types.contains(&t.to_string()) {
+                                    self.unresolved_types.push(t.to_string());
+                                }
+                            }
 
-                        fs::rename(f.to_string(), f.to_string() + ".old").unwrap();
-                        fs::rename(f.to_string() + ".new", f.to_string()).unwrap();
-                        fs::remove_file(f.to_string() + ".old").unwrap();
+                            let mut pulled = Vec::new();
+                            for def in closure.symbols {
+                                input = fnv1a64(input, def.text.as_bytes());
+                                if self.compress {
+                                    let text = compress_declaration(&def.text);
+                                    if !text.is_empty()
+                                        && declared.insert((name.clone(), text.clone()))
+                                    {
+                                        pulled.push(text);
+                                    }
+                                } else {
+                                    pulled.push(def.text.clone());
+                                }
+                            }
+                            if !self.compress && !pulled.is_empty() && !section.is_empty() {
+                                pulled.push(String::new());
+                            }
+                            pulled.append(&mut section);
+                            section = pulled;
+                        }
+                        sections.push(FromSection {
+                            name,
+                            labeled: true,
+                            lines: section,
+                            provides,
+                            input_hash: input,
+                        });
+                    }
+                }
+                FromFileType::GnuLinkScript => {
+                    // the values of a region may use any part of the script
+                    let input = fnv1a64(FNV1A64_INIT, text.as_bytes());
+                    for (name, mut script) in self.read_link_script(&text) {
+                        script.symbols.retain(|sym| !is_ignored(&sym.name));
+                        sections.push(FromSection {
+                            labeled: name.is_some(),
+                            name: name.unwrap_or_default(),
+                            lines: script.to_c_lines(),
+                            provides: Vec::new(),
+                            input_hash: input,
+                        });
+                    }
+                }
+                FromFileType::Elf => {
+                    let mut elf = ElfFile::parse(&data).map_err(|message| SyncError::Elf {
+                        path: f.to_string(),
+                        message,
+                    })?;
+                    elf.symbols.retain(|sym| !is_ignored(&sym.name));
+                    if self.elf_output == ElfOutput::Extern {
+                        for warning in &elf.warnings {
+                            self.warnings.push(format!("{}: {}", f, warning));
+                        }
                     }
+                    sections.push(FromSection {
+                        name: String::new(),
+                        labeled: false,
+                        lines: elf.to_c_lines(self.elf_output),
+                        provides: Vec::new(),
+                        input_hash: fnv1a64(FNV1A64_INIT, &data),
+                    });
                 }
-                Err(e) => {
-                    println!("{} open failed, {}", &f, e);
+                FromFileType::GnuMap => {
+                    let mut map = MapFile::parse(&text);
+                    map.sections.retain(|s| !is_ignored(&s.name));
+                    map.symbols.retain(|sym| !is_ignored(&sym.name));
+                    sections.push(FromSection {
+                        name: String::new(),
+                        labeled: false,
+                        lines: map.to_c_lines(self.map_output),
+                        provides: Vec::new(),
+                        input_hash: fnv1a64(FNV1A64_INIT, text.as_bytes()),
+                    });
                 }
             }
+
+            for FromSection {
+                name,
+                labeled,
+                lines: mut section,
+                mut provides,
+                input_hash: input,
+            } in sections
+            {
+                let i = match blocks.iter().position(|b| b.name == name) {
+                    Some(i) => i,
+                    None => {
+                        let mut lines = Vec::new();
+                        if !self.class_name.is_empty() {
+                            lines.push(format!("// {}", self.class_name));
+                        }
+                        blocks.push(Block {
+                            name: name.clone(),
+                            provide: addresses.as_ref().map(|_| lines.clone()),
+                            lines,
+                            has_section: false,
+                            jump_table: None,
+                            sources: Vec::new(),
+                            input_hash: settings_hash,
+                        });
+                        blocks.len() - 1
+                    }
+                };
+                let block = &mut blocks[i];
+                let file_name = Path::new(f)
+                    .file_name()
+                    .map_or(f.into(), |name| name.to_string_lossy())
+                    .to_string();
+                if !block.sources.contains(&file_name) {
+                    block.sources.push(file_name);
+                }
+                block.input_hash = fnv1a64(block.input_hash, &input.to_le_bytes());
+                let source_comment = self.source_comment(f, labeled.then_some(name.as_str()));
+
+                if let Some(provide) = block.provide.as_mut().filter(|_| !provides.is_empty()) {
+                    if provide.len() > usize::from(!self.class_name.is_empty()) && !self.compress {
+                        provide.push(String::new());
+                    }
+                    provide.push(source_comment.clone());
+                    provide.append(&mut provides);
+                }
+                if section.is_empty() {
+                    continue;
+                }
+                if block.has_section && !self.compress {
+                    block.lines.push(String::new());
+                }
+                block.has_section = true;
+                block.lines.push(source_comment);
+                block.lines.append(&mut section);
+            }
+        }
+
+        self.unmatched_ignore_symbols = ignore
+            .iter()
+            .zip(ignore_matched)
+            .filter(|(_, matched)| !matched)
+            .map(|(pattern, _)| pattern.as_str().to_string())
+            .collect();
+        for rule in &self.unmatched_ignore_symbols {
+            self.warnings
+                .push(format!("ignore symbol {} matched nothing", rule));
+        }
+        for (rule, _) in keep
+            .iter()
+            .zip(keep_matched)
+            .filter(|(_, matched)| !matched)
+        {
+            self.warnings
+                .push(format!("keep symbol {} matched nothing", rule.as_str()));
+        }
+        for (rule, _) in marks
+            .iter()
+            .zip(marks_matched)
+            .filter(|(_, matched)| !matched)
+        {
+            self.warnings
+                .push(format!("mark symbol {} matched nothing", rule.as_str()));
+        }
+        for block in &mut blocks {
+            block.jump_table = tables.remove(&block.name);
+            if let Some(table) = &mut block.jump_table {
+                self.keep_jump_indices(table, &block.name, &jump_rules)?;
+            }
+        }
+        for rule in jump_rules
+            .iter()
+            .filter(|r| tables.contains_key(r.region()))
+        {
+            self.warnings
+                .push(format!("jump table {} matched no region", rule.as_str()));
+        }
+
+        Ok(blocks)
+    }
+
+    /// Keeps the indices a jump table was written with in the header To files
+    fn keep_jump_indices(
+        &self,
+        table: &mut JumpTable,
+        region: &str,
+        rules: &[JumpTableRule],
+    ) -> Result<(), SyncError> {
+        for f in &self.to {
+            if JumpTableOutput::for_path(f) != JumpTableOutput::Header {
+                continue;
+            }
+            let (text, _) = read_text(f, self.encoding)?;
+            for r in self.get_regions(&text, "autogen start", "autogen end") {
+                if r.name != region {
+                    continue;
+                }
+                let written = table.written_indices(&block_lines(&text, &r));
+                table
+                    .keep_indices(&written)
+                    .map_err(|message| SyncError::JumpTable {
+                        rule: rules
+                            .iter()
+                            .find(|rule| rule.region() == region)
+                            .map_or(String::new(), |rule| rule.as_str().to_string()),
+                        message: format!("{} in {}", message, f),
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tidies the autogen blocks of the To files as they are, without syncing,
+    /// returns the changes written. GNU ld To files are left as they are.
+    pub fn tidy(&mut self) -> Result<Vec<TargetChange>, SyncError> {
+        let stamp_prefix = format!("// {}:", label_words(&self.label));
+        let mut written = Vec::new();
+        let mut warnings = Vec::new();
+
+        for f in &self.to {
+            if JumpTableOutput::for_path(f) == JumpTableOutput::LinkScript {
+                continue;
+            }
+            let (text, format) = read_text(f, self.encoding)?;
+
+            let regions = self.get_regions(&text, "autogen start", "autogen end");
+            let mut replaced = Vec::new();
+            let mut changed_regions = Vec::new();
+            for region in &regions {
+                let mut old: Vec<String> = block_lines(&text, region)
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect();
+                let stamp = match old.first() {
+                    Some(line) if Stamp::is_stamp(&stamp_prefix, line) => Some(old.remove(0)),
+                    _ => None,
+                };
+
+                let mut new_block = self.tidy_lines(f, &region.name, old, &mut warnings);
+                new_block.splice(0..0, stamp);
+                if block_lines(&text, region).join("\n") != new_block.join("\n") {
+                    changed_regions.push(region.name.clone());
+                }
+                replaced.push((region, new_block));
+            }
+
+            let new = self.replace_blocks(&text, &replaced);
+            if new == text {
+                continue;
+            }
+            if self.history > 0 {
+                self.save_history(f, &text, &new)?;
+            }
+            self.write_target(f, &new, &format)?;
+            written.push(TargetChange {
+                path: f.to_string(),
+                old: text,
+                new,
+                regions: changed_regions,
+                format,
+            });
+        }
+        self.warnings = warnings;
+
+        Ok(written)
+    }
+
+    /// Returns the lines of an autogen block tidied, the comments naming the From files
+    /// are dropped as the declarations are reordered. The lines are kept as they are,
+    /// with a warning, if the block can't be reordered.
+    fn tidy_lines(
+        &self,
+        f: &str,
+        region: &str,
+        lines: Vec<String>,
+        warnings: &mut Vec<String>,
+    ) -> Vec<String> {
+        let banners = self.banners(region);
+        let code: Vec<&str> = lines
+            .iter()
+            .map(|l| l.as_str())
+            .filter(|l| !banners.iter().any(|b| b == l) && !self.is_source_comment(l, region))
+            .collect();
+
+        match tidy_block(&code) {
+            Some(mut tidied) => {
+                if !self.class_name.is_empty() {
+                    tidied.insert(0, format!("// {}", self.class_name));
+                }
+                tidied
+            }
+            None => {
+                warnings.push(format!(
+                    "{} of {} has code that can't be reordered, not tidied",
+                    autogen_words(region, "block"),
+                    f
+                ));
+                lines
+            }
+        }
+    }
+
+    /// Tells whether a line is the comment naming the origin of a sub-section of a region,
+    /// e.g. `// test1.h: header-sync`, whether the file is a From file or not
+    fn is_source_comment(&self, line: &str, region: &str) -> bool {
+        let label = match region {
+            "" => label_words(&self.label),
+            _ => format!("{}:{}", label_words(&self.label), region),
+        };
+        line.strip_prefix("// ")
+            .and_then(|comment| comment.strip_suffix(&label))
+            .and_then(|file| file.strip_suffix(": "))
+            .is_some_and(|file| !file.trim().is_empty())
+    }
+
+    /// Returns the comment lines `generate_blocks` puts above the block of a region
+    /// and its sections
+    fn banners(&self, region: &str) -> Vec<String> {
+        let mut banners = vec![format!("// {}", self.class_name)];
+        for f in &self.from {
+            banners.push(self.source_comment(f, Some(region)));
+            banners.push(self.source_comment(f, None));
+        }
+        banners
+    }
+
+    /// Returns `text` with the given autogen blocks replaced, `blocks` are in the order
+    /// of `text`
+    fn replace_blocks(&self, text: &str, blocks: &[(&Region, Vec<String>)]) -> String {
+        let mut new = String::new();
+        let mut pos = 0;
+
+        for (region, block) in blocks {
+            // the start label line is written again below, the other lines are kept as they are
+            let before = &text[pos..region.start];
+            let label = before[..before.len() - 1].rfind('\n').map_or(0, |p| p + 1);
+            new.push_str(&before[..label]);
+
+            new.push_str(&self.label_line(&autogen_words(&region.name, "start")));
+            new.push('\n');
+            for line in block {
+                new.push_str(line);
+                new.push('\n');
+            }
+            new.push_str(&self.label_line(&autogen_words(&region.name, "end")));
+            new.push('\n');
+            pos = region.end;
+        }
+
+        new.push_str(&text[pos..]);
+        new
+    }
+
+    /// Returns the label with `word` inserted before its last part,
+    /// e.g. `/* header-sync autogen start */`
+    fn label_line(&self, word: &str) -> String {
+        let mut label: Vec<&str> = self.label.split(' ').collect();
+        let label_last = label.pop().unwrap();
+
+        label.push(word);
+        label.push(label_last);
+        label.join(" ")
+    }
+
+    /// Returns the C preprocessor and parser settings of the `ast` parse mode for a header
+    fn cpp_config(&self, format: &TextFormat) -> Result<Config, SyncError> {
+        let mut config = match &self.toolchain {
+            Some(toolchain) => toolchain.config().map_err(|message| SyncError::Toolchain {
+                command: toolchain.command().to_string(),
+                message,
+            })?,
+            None => Config::default(),
+        };
+        if let Some(pp) = &self.preprocessor {
+            config.cpp_options.extend(pp.cpp_options());
+        }
+        // clang only reads UTF-8 and has no such option
+        if format.encoding() != "UTF-8" && config.flavor == Flavor::GnuC11 {
+            config
+                .cpp_options
+                .push(format!("-finput-charset={}", format.encoding()));
+        }
+        Ok(config)
+    }
+
+    /// Returns for each line of a From header whether the preprocessor keeps it,
+    /// and a warning for each `#if` it can't evaluate.
+    /// In the `ast` parse mode the C preprocessor tells which directives are kept.
+    fn kept_lines(
+        &self,
+        pp: &Preprocessor,
+        f: &str,
+        text: &str,
+        format: &TextFormat,
+    ) -> Result<(Vec<bool>, Vec<String>), SyncError> {
+        match self.parse_mode {
+            ParseMode::Ast => {
+                let config = self.cpp_config(format)?;
+                let directives =
+                    active_directives(&config, f).map_err(|source| SyncError::Parse {
+                        path: f.to_string(),
+                        source,
+                    })?;
+                let kept = text
+                    .lines()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        !line.trim_start().starts_with('#') || directives.contains(&(i + 1))
+                    })
+                    .collect();
+                Ok((kept, Vec::new()))
+            }
+            ParseMode::Text => {
+                pp.kept_lines(f, text, self.encoding)
+                    .map_err(|message| SyncError::Preprocess {
+                        path: f.to_string(),
+                        message,
+                    })
+            }
         }
-        fs::remove_file(&tmp_name).unwrap();
     }
 
-    /// Returns the position of the label in the given file
-    fn get_label_position_in_file(
+    /// Returns the declarations in the given lines of a header,
+    /// and a warning for each line the AST parser skipped.
+    /// `kept` tells for each line of the header whether the preprocessor keeps it.
+    fn read_symbols(
         &self,
-        file: &File,
-        start: &str,
-        end: &str,
-    ) -> Option<(usize, usize)> {
-        let mut reader = BufReader::new(file);
-        let mut line = String::new();
-        let mut res = None;
-        let mut start_pos = 0;
-        let end_pos;
-
-        // need to rewind the file
-        reader.rewind().unwrap();
-
-        while let Ok(size) = reader.read_line(&mut line) {
-            if size == 0 {
-                break;
-            }
-            line = line.trim_end().to_string();
-            match self.check_label(&line, start, end) {
-                CheckLabelRsp::Start => {
-                    start_pos = reader.stream_position().unwrap();
+        f: &str,
+        text: &str,
+        format: &TextFormat,
+        lines: Range<usize>,
+        kept: Option<&[bool]>,
+    ) -> Result<(Vec<Symbol>, Vec<String>), SyncError> {
+        let is_kept = |line: usize| kept.is_none_or(|kept| kept[line - 1]);
+
+        match self.parse_mode {
+            ParseMode::Ast => {
+                let config = self.cpp_config(format)?;
+                let (mut symbols, skipped) =
+                    parse_symbols_text(&config, f, text, lines).map_err(|source| {
+                        SyncError::Parse {
+                            path: f.to_string(),
+                            source,
+                        }
+                    })?;
+                // the `#define`s are read from the source, the preprocessor tells which are kept
+                symbols.retain(|sym| sym.kind != SymbolKind::Macro || is_kept(sym.line));
+                let warnings = skipped
+                    .into_iter()
+                    .filter(|line| is_kept(*line))
+                    .map(|line| {
+                        let code = text.lines().nth(line - 1).unwrap_or_default().trim();
+                        format!("{}:{}: {} is not synced in AST mode", f, line, code)
+                    })
+                    .collect();
+                Ok((symbols, warnings))
+            }
+            ParseMode::Text => {
+                // source line number of each line kept
+                let region: Vec<(usize, &str)> = text
+                    .lines()
+                    .enumerate()
+                    .skip(lines.start - 1)
+                    .take(lines.len())
+                    .map(|(i, line)| (i + 1, line))
+                    .filter(|(line, _)| is_kept(*line))
+                    .collect();
+                let region_lines: Vec<&str> = region.iter().map(|(_, line)| *line).collect();
+
+                let mut symbols = split_symbols(&region_lines, 1);
+                for sym in &mut symbols {
+                    sym.line = region[sym.line - 1].0;
                 }
-                CheckLabelRsp::End => {
-                    end_pos = reader.stream_position().unwrap();
-                    res = Some((start_pos as usize, end_pos as usize));
-                    break;
+                Ok((symbols, Vec::new()))
+            }
+        }
+    }
+
+    /// Returns the comment naming the origin of a sub-section, e.g. `// test1.h: header-sync`,
+    /// or `// test1.h: header-sync:gpio` for the region named `gpio`.
+    /// The label is left out if `region` is `None`.
+    fn source_comment(&self, f: &str, region: Option<&str>) -> String {
+        let name = Path::new(f)
+            .file_name()
+            .map_or(f.into(), |name| name.to_string_lossy());
+
+        match region {
+            None => format!("// {}", name),
+            Some("") => format!("// {}: {}", name, label_words(&self.label)),
+            Some(region) => format!("// {}: {}:{}", name, label_words(&self.label), region),
+        }
+    }
+
+    /// Returns the symbols of each region of a GNU ld script,
+    /// the whole script is used with no region name if it has no label
+    fn read_link_script(&self, text: &str) -> Vec<(Option<String>, LdScript)> {
+        let regions = self.get_regions(text, "start", "end");
+        if regions.is_empty() {
+            return vec![(None, LdScript::parse(text))];
+        }
+
+        let ranges: Vec<Range<usize>> = regions.iter().map(|r| r.start..r.end).collect();
+        regions
+            .into_iter()
+            .map(|r| Some(r.name))
+            .zip(LdScript::parse_ranges(text, &ranges))
+            .collect()
+    }
+
+    /// Returns the name and lines (1-based) of each region in the given text
+    fn get_label_lines(&self, text: &str) -> Vec<(String, Range<usize>)> {
+        self.get_regions(text, "start", "end")
+            .into_iter()
+            .map(|r| {
+                let first = text[..r.start].matches('\n').count() + 1;
+                let mut end_label = text[..r.end].matches('\n').count();
+                if !text[..r.end].ends_with('\n') {
+                    end_label += 1;
                 }
-                _ => {}
+
+                (r.name, first..end_label)
+            })
+            .collect()
+    }
+
+    /// Returns the regions between the labels in the given text
+    fn get_regions(&self, text: &str, start: &str, end: &str) -> Vec<Region> {
+        let mut regions = Vec::new();
+        let mut open: Option<(String, usize)> = None;
+        let mut pos = 0;
+
+        for line in text.split_inclusive('\n') {
+            pos += line.len();
+            match self.check_label(line.trim_end(), start, end) {
+                CheckLabelRsp::Start(name) => open = Some((name, pos)),
+                CheckLabelRsp::End(name) => {
+                    if let Some((open_name, start_pos)) = open.take() {
+                        if open_name == name {
+                            regions.push(Region {
+                                name,
+                                start: start_pos,
+                                end: pos,
+                            });
+                        } else {
+                            open = Some((open_name, start_pos));
+                        }
+                    }
+                }
+                CheckLabelRsp::None => {}
             }
-            line.clear();
         }
 
-        res
+        regions
     }
 
+    /// Checks if the line is a start or end label, a region name may follow
+    /// the word before `start`/`end` after a `:`, e.g. `/* header-sync:gpio start */`
     fn check_label(&self, line: &str, start: &str, end: &str) -> CheckLabelRsp {
         let label: Vec<&str> = self.label.split(' ').collect();
         let mut need_copy = CheckLabelRsp::None;
 
-        let iter_line: Vec<&str> = line.split(' ').collect();
-        if label.iter().all(|x| iter_line.contains(&x)) {
+        let mut name = String::new();
+        let iter_line: Vec<&str> = line
+            .split(' ')
+            .map(|w| match w.split_once(':') {
+                Some((word, region))
+                    if !region.is_empty()
+                        && (label.contains(&word)
+                            || start.split(' ').any(|x| x == word)
+                            || end.split(' ').any(|x| x == word)) =>
+                {
+                    name = region.to_string();
+                    word
+                }
+                _ => w,
+            })
+            .collect();
+        if label.iter().all(|x| iter_line.contains(x)) {
             // println!(
             //     "check if start or end, line:{:?} start:{} end:{}",
             //     iter_line, start, end
@@ -244,11 +1412,11 @@ impl Syncer {
             if start.split(' ').count() + label.len() == iter_line.len()
                 && start.split(' ').all(|x| iter_line.contains(&x))
             {
-                need_copy = CheckLabelRsp::Start;
+                need_copy = CheckLabelRsp::Start(name);
             } else if end.split(' ').count() + label.len() == iter_line.len()
                 && end.split(' ').all(|x| iter_line.contains(&x))
             {
-                need_copy = CheckLabelRsp::End;
+                need_copy = CheckLabelRsp::End(name);
             }
         }
 
@@ -256,17 +1424,88 @@ impl Syncer {
     }
 }
 
+/// A labeled part of a file, `start` and `end` are the offsets right after
+/// the start label line and the end label line
+struct Region {
+    name: String,
+    start: usize,
+    end: usize,
+}
+
+/// The lines generated for the autogen block of a region
+/// What a From file has for a region
+struct FromSection {
+    name: String,
+    /// Whether the region is labeled, rather than the whole file
+    labeled: bool,
+    lines: Vec<String>,
+    /// The `PROVIDE` lines of its declarations
+    provides: Vec<String>,
+    /// Hash of the text the lines are generated from
+    input_hash: u64,
+}
+
+struct Block {
+    name: String,
+    lines: Vec<String>,
+    has_section: bool,
+    /// The jump table written instead of the declarations of the region
+    jump_table: Option<JumpTable>,
+    /// The `PROVIDE` lines written into GNU ld To files, if there is an address source
+    provide: Option<Vec<String>>,
+    /// File names of the From files with the region
+    sources: Vec<String>,
+    /// Hash of the settings, the address source and the regions the block is generated from
+    input_hash: u64,
+}
+
+/// Returns the lines of a region, without the end label
+fn block_lines<'a>(text: &'a str, region: &Region) -> Vec<&'a str> {
+    let mut lines: Vec<&str> = text[region.start..region.end].lines().collect();
+    lines.pop();
+    lines
+}
+
+/// Returns the words inserted into the label of an autogen block, e.g. `autogen:gpio start`
+fn autogen_words(region: &str, word: &str) -> String {
+    if region.is_empty() {
+        format!("autogen {}", word)
+    } else {
+        format!("autogen:{} {}", region, word)
+    }
+}
+
+/// Returns the label without its comment marks, e.g. `header-sync`
+fn label_words(label: &str) -> String {
+    label
+        .split(' ')
+        .filter(|w| !matches!(*w, "/*" | "*/" | "//"))
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test_util;
+
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
+    use crate::test_util::{autogen_block, example, SyncTest, TestDir, LABEL};
     use crate::Syncer;
-    // use lang_c::driver::{parse, Config};
-    use std::env;
 
     #[test]
     fn test_check_update_status() {
-        let from = vec![concat!(env!("CARGO_MANIFEST_DIR"), "\\examples\\test1.h")];
-        let to = vec![concat!(env!("CARGO_MANIFEST_DIR"), "\\examples\\api.h")];
-        let mut syncer = Syncer::new(from, to, "/* header-sync */");
+        let dir = TestDir::new("check_update_status");
+        let from = dir.join("test1.h");
+        let to = dir.join("api.h");
+        fs::copy(example("test1.h"), &from).unwrap();
+        fs::copy(example("api.h"), &to).unwrap();
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
 
         // let mut sys_path = env::var_os("path").unwrap();
         // sys_path.push(";");
@@ -278,7 +1517,7 @@ mod tests {
         // let config = Config::default();
         // let res = parse(
         //     &config,
-        //     concat!(env!("CARGO_MANIFEST_DIR"), "\\examples\\test1.h"),
+        //     concat!(env!("CARGO_MANIFEST_DIR"), "/examples/test1.h"),
         // );
 
         // // format!("{:?}", res);
@@ -302,6 +1541,114 @@ mod tests {
         //     }
         //     Err(_) => {}
         // };
-        syncer.run();
+        syncer.run().unwrap();
+        assert!(fs::read_to_string(&to)
+            .unwrap()
+            .contains("/* header-sync autogen start */\n// test1.h: header-sync\nstruct test1\n"));
+    }
+    #[test]
+    fn test_ignore_symbols() {
+        let dir = TestDir::new("ignore_symbols");
+        let from = dir.join("test2.h");
+        fs::copy(example("test2.h"), &from).unwrap();
+        let to = dir.write("api.h", autogen_block("", ""));
+
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_ignore_symbols(vec!["test2_cfg_t", "TEST2_*", "/_value$/", "unknown"]);
+        syncer.run().unwrap();
+
+        let res = fs::read_to_string(&to).unwrap();
+        assert!(!res.contains("TEST2_MAX"));
+        assert!(!res.contains("typedef struct"));
+        assert!(!res.contains("Configuration of test2"));
+        assert!(!res.contains("union test2_value"));
+        assert!(res.contains("enum test2_state {"));
+        assert!(
+            res.contains("int test2_init(const test2_cfg_t *cfg,\n               test2_cb_t cb);")
+        );
+        assert_eq!(syncer.unmatched_ignore_symbols(), ["unknown"]);
+        assert_eq!(syncer.warnings(), ["ignore symbol unknown matched nothing"]);
+    }
+    #[test]
+    fn test_source_sections() {
+        let dir = TestDir::new("source_sections");
+        let to = dir.write("api.h", autogen_block("", ""));
+
+        let mut syncer = Syncer::new(
+            vec![&example("test1.h"), &example("test2.h")],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_class_name("drivers");
+        syncer.run().unwrap();
+
+        let res = fs::read_to_string(&to).unwrap();
+        assert!(res.starts_with(
+            "/* header-sync autogen start */\n// drivers\n// test1.h: header-sync\nstruct test1\n"
+        ));
+        assert!(res.contains(
+            "extern void func2(void);\n\n// test2.h: header-sync\n#define TEST2_MAX 16\n"
+        ));
+    }
+    #[test]
+    fn test_named_regions() {
+        let test = SyncTest::new("named_regions", "");
+        fs::write(
+            &test.from,
+            "/* header-sync:gpio start */\nvoid gpio_init(void);\n/* header-sync:gpio end */\n\
+             /* header-sync start */\nvoid gpio_irq(void);\n/* header-sync end */\n",
+        )
+        .unwrap();
+        let uart = test.dir.write(
+            "uart.h",
+            "/* header-sync:uart start */\nvoid uart_init(void);\n/* header-sync:uart end */\n",
+        );
+        fs::write(
+            &test.to,
+            [
+                autogen_block("uart", ""),
+                autogen_block("spi", "void spi_init(void);\n"),
+                autogen_block("", ""),
+                autogen_block("gpio", ""),
+            ]
+            .concat(),
+        )
+        .unwrap();
+
+        let mut syncer = Syncer::new(
+            vec![&test.from, uart.to_str().unwrap()],
+            vec![&test.to],
+            LABEL,
+        );
+        syncer.run().unwrap();
+
+        assert_eq!(
+            test.target(),
+            [
+                autogen_block(
+                    "uart",
+                    "// uart.h: header-sync:uart\nvoid uart_init(void);\n"
+                ),
+                autogen_block("spi", "void spi_init(void);\n"),
+                autogen_block("", "// test1.h: header-sync\nvoid gpio_irq(void);\n"),
+                autogen_block(
+                    "gpio",
+                    "// test1.h: header-sync:gpio\nvoid gpio_init(void);\n"
+                ),
+            ]
+            .concat()
+        );
+
+        fs::write(
+            &uart,
+            "/* header-sync:uart start */\nvoid uart_init(int baud);\n/* header-sync:uart end */\n",
+        )
+        .unwrap();
+        let changes = syncer.plan().unwrap();
+        assert_eq!(changes[0].regions, ["uart"]);
     }
 }
@@ -0,0 +1,297 @@
+use crate::lds::c_macro_name;
+use crate::LdMemoryRegion;
+
+/// What is written for a GNU ld map file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapOutput {
+    /// `#define`s of the memory regions, the address and size of the output sections,
+    /// and the address of the symbols
+    Defines,
+    /// `{ "<symbol>", 0x... },` rows of a symbol table
+    Table,
+}
+
+/// An output section placed by the linker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapSection {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    /// Address the section is loaded at, if it is not `address`
+    pub load_address: Option<u64>,
+}
+
+/// A symbol defined by an object file or the link script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapSymbol {
+    pub name: String,
+    pub address: u64,
+    /// Output section listing the symbol
+    pub section: String,
+}
+
+/// Memory configuration, output sections and symbols of a GNU ld map file (`-Map=...`)
+#[derive(Debug, Default)]
+pub struct MapFile {
+    pub memory: Vec<LdMemoryRegion>,
+    pub sections: Vec<MapSection>,
+    pub symbols: Vec<MapSymbol>,
+}
+
+/// Sections that are not loaded, e.g. debug info
+const UNLOADED_SECTIONS: [&str; 8] = [
+    ".debug",
+    ".comment",
+    ".note.GNU-stack",
+    ".gnu.attributes",
+    ".ARM.attributes",
+    ".riscv.attributes",
+    ".stab",
+    ".zdebug",
+];
+
+impl MapFile {
+    pub fn parse(text: &str) -> MapFile {
+        let mut map = MapFile::default();
+        let mut part = "";
+        // an output section whose address is on the next line
+        let mut pending: Option<String> = None;
+
+        for line in text.lines() {
+            match line.trim_end() {
+                "Memory Configuration"
+                | "Linker script and memory map"
+                | "Cross Reference Table" => {
+                    part = line.trim_end();
+                    continue;
+                }
+                "" => continue,
+                _ => {}
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            match part {
+                "Memory Configuration" => {
+                    if tokens.len() < 3 || tokens[0] == "Name" || tokens[0] == "*default*" {
+                        continue;
+                    }
+                    map.memory.push(LdMemoryRegion {
+                        name: tokens[0].to_string(),
+                        origin: parse_hex(tokens[1]),
+                        length: parse_hex(tokens[2]),
+                    });
+                }
+                "Linker script and memory map" => {
+                    let is_output_section = !line.starts_with(' ')
+                        && (tokens.len() == 1 || tokens[1].starts_with("0x"));
+                    if is_output_section && tokens.len() == 1 {
+                        pending = Some(tokens[0].to_string());
+                        continue;
+                    }
+                    let name = match pending.take() {
+                        Some(name) if line.starts_with(' ') && tokens[0].starts_with("0x") => {
+                            Some(name)
+                        }
+                        _ if is_output_section => Some(tokens[0].to_string()),
+                        _ => None,
+                    };
+                    if let Some(name) = name {
+                        let fields = &tokens[tokens.len() - line_fields(&tokens, &name)..];
+                        map.push_section(name, fields);
+                        continue;
+                    }
+
+                    if let Some(sym) = parse_symbol_line(&tokens) {
+                        let section = map.sections.last().map_or("", |s| s.name.as_str());
+                        map.symbols.push(MapSymbol {
+                            name: sym.0,
+                            address: sym.1,
+                            section: section.to_string(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        map.sections
+            .retain(|s| s.size > 0 && !UNLOADED_SECTIONS.iter().any(|u| s.name.starts_with(u)));
+        map.symbols
+            .retain(|sym| !UNLOADED_SECTIONS.iter().any(|u| sym.section.starts_with(u)));
+        map
+    }
+
+    fn push_section(&mut self, name: String, fields: &[&str]) {
+        let (address, size) = match (
+            fields.first().and_then(|f| parse_hex(f)),
+            fields.get(1).and_then(|f| parse_hex(f)),
+        ) {
+            (Some(address), Some(size)) => (address, size),
+            _ => return,
+        };
+        let load_address = match fields.get(2..5) {
+            Some(["load", "address", load]) => parse_hex(load),
+            _ => None,
+        };
+
+        self.sections.push(MapSection {
+            name,
+            address,
+            size,
+            load_address,
+        });
+    }
+
+    /// Returns the C lines for the map file
+    pub fn to_c_lines(&self, output: MapOutput) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        if output == MapOutput::Table {
+            for sym in &self.symbols {
+                lines.push(format!("{{ \"{}\", 0x{:08x} }},", sym.name, sym.address));
+            }
+            return lines;
+        }
+
+        for region in &self.memory {
+            let name = c_macro_name(&region.name);
+            if let Some(origin) = region.origin {
+                lines.push(format!("#define {}_ORIGIN 0x{:08x}", name, origin));
+            }
+            if let Some(length) = region.length {
+                lines.push(format!("#define {}_LENGTH 0x{:08x}", name, length));
+            }
+        }
+
+        for section in &self.sections {
+            let name = format!(
+                "SECTION_{}",
+                c_macro_name(section.name.trim_start_matches('.'))
+            );
+            lines.push(format!("#define {}_ADDR 0x{:08x}", name, section.address));
+            lines.push(format!("#define {}_SIZE 0x{:08x}", name, section.size));
+            if let Some(load_address) = section.load_address {
+                lines.push(format!("#define {}_LOAD_ADDR 0x{:08x}", name, load_address));
+            }
+        }
+
+        for sym in &self.symbols {
+            lines.push(format!(
+                "#define {}_ADDR 0x{:08x}",
+                c_macro_name(&sym.name),
+                sym.address
+            ));
+        }
+
+        lines
+    }
+}
+
+/// Returns the number of fields after the section name on an output section line
+fn line_fields(tokens: &[&str], name: &str) -> usize {
+    match tokens.first() {
+        Some(first) if *first == name => tokens.len() - 1,
+        _ => tokens.len(),
+    }
+}
+
+/// Returns the name and address of a symbol line, e.g. `0x00001000  rom_add`
+/// or `0x20003000  shared_mem = (ORIGIN (RAM) + 0x3000)`
+fn parse_symbol_line(tokens: &[&str]) -> Option<(String, u64)> {
+    let address = parse_hex(tokens.first()?)?;
+    let rest = tokens.get(1..)?.join(" ");
+
+    let name = match rest.split_once('=') {
+        Some((name, _)) => {
+            let name = name.trim();
+            name.strip_prefix("PROVIDE (")
+                .or_else(|| name.strip_prefix("PROVIDE_HIDDEN ("))
+                .or_else(|| name.strip_prefix("HIDDEN ("))
+                .unwrap_or(name)
+                .trim()
+                .to_string()
+        }
+        None if tokens.len() == 2 => rest,
+        None => return None,
+    };
+
+    // `. = ALIGN (0x4)`, input sections and sizes
+    let is_symbol = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+        && !name.starts_with("0x")
+        && name != ".";
+    is_symbol.then_some((name, address))
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{autogen_block, example, TestDir, LABEL};
+    use crate::{FromFileType, MapFile, MapOutput, Syncer};
+
+    #[test]
+    fn test_map() {
+        let map = MapFile::parse(&fs::read_to_string(example("rom.map")).unwrap());
+        assert_eq!(map.memory.len(), 2);
+        assert_eq!(map.memory[1].origin, Some(0x2000_0000));
+        assert_eq!(map.memory[1].length, Some(0x4000));
+        let names: Vec<&str> = map.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, [".text", ".rodata", ".eh_frame", ".data", ".bss"]);
+        assert_eq!(map.sections[3].load_address, Some(0x11a0));
+        let names: Vec<&str> = map.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "rom_add",
+                "rom_init",
+                "rom_printf",
+                "rom_reset",
+                "rom_hidden",
+                "_start",
+                "rom_name",
+                "rom_version",
+                "__bss_start",
+                "rom_cfgs",
+                "__bss_end",
+                "shared_mem"
+            ]
+        );
+        assert_eq!(map.symbols[9].section, ".bss");
+
+        let dir = TestDir::new("map");
+        let to = dir.join("api.h");
+        for (output, expected) in [
+            (
+                MapOutput::Defines,
+                "#define ROM_ORIGIN 0x00001000\n#define ROM_LENGTH 0x00010000\n\
+                 #define RAM_ORIGIN 0x20000000\n#define RAM_LENGTH 0x00004000\n\
+                 #define SECTION_TEXT_ADDR 0x00001000\n#define SECTION_TEXT_SIZE 0x000000b7\n\
+                 #define SECTION_DATA_ADDR 0x20000000\n#define SECTION_DATA_SIZE 0x00000004\n\
+                 #define SECTION_DATA_LOAD_ADDR 0x000011a0\n\
+                 #define ROM_ADD_ADDR 0x00001000\n#define ROM_VERSION_ADDR 0x20000000\n",
+            ),
+            (
+                MapOutput::Table,
+                "{ \"rom_add\", 0x00001000 },\n{ \"rom_version\", 0x20000000 },\n",
+            ),
+        ] {
+            fs::write(&to, autogen_block("", "")).unwrap();
+            let mut syncer =
+                Syncer::new(vec![&example("rom.map")], vec![to.to_str().unwrap()], LABEL);
+            syncer.set_type_of_form(FromFileType::GnuMap);
+            syncer.set_map_output(output);
+            syncer.set_keep_symbols(vec!["rom_add", "rom_version", ".text", ".data"]);
+            syncer.run().unwrap();
+            assert_eq!(
+                fs::read_to_string(&to).unwrap(),
+                autogen_block("", &format!("// rom.map\n{}", expected))
+            );
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{Symbol, SymbolKind, SymbolPattern};
+
+/// Decorates the symbols matching a pattern, the rule is `<pattern>=<mark>`,
+/// see [`SymbolPattern`] for the pattern. The mark is one of
+/// + `weak`, for `__attribute__((weak))`
+/// + `deprecated`, for `__attribute__((deprecated))`
+/// + `section:<name>`, for `__attribute__((section("<name>")))`
+/// + anything else, written as it is, e.g. `ROM_API`
+#[derive(Debug, Clone)]
+pub struct MarkRule {
+    rule: String,
+    pattern: SymbolPattern,
+    mark: String,
+}
+
+impl MarkRule {
+    pub fn new(rule: &str) -> Result<MarkRule, String> {
+        let (pattern, mark) = rule
+            .split_once('=')
+            .filter(|(pattern, mark)| !pattern.is_empty() && !mark.is_empty())
+            .ok_or("expected <pattern>=<mark>".to_string())?;
+        let pattern = SymbolPattern::new(pattern).map_err(|e| e.to_string())?;
+
+        let mark = match mark {
+            "weak" => "__attribute__((weak))".to_string(),
+            "deprecated" => "__attribute__((deprecated))".to_string(),
+            _ => match mark.strip_prefix("section:") {
+                Some(section) => format!("__attribute__((section(\"{}\")))", section),
+                None => mark.to_string(),
+            },
+        };
+
+        Ok(MarkRule {
+            rule: rule.to_string(),
+            pattern,
+            mark,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.rule
+    }
+
+    /// Returns whether the rule applies to the symbol, macros and other parts can't be marked
+    pub fn is_match(&self, sym: &Symbol) -> bool {
+        !matches!(sym.kind, SymbolKind::Macro | SymbolKind::Other)
+            && self.pattern.is_match(&sym.name)
+    }
+
+    pub fn mark(&self) -> &str {
+        &self.mark
+    }
+}
+
+/// Returns the text of a symbol with the marks added, e.g. `ROM_API int f(void);`.
+///
+/// The marks go in front of the declaration, after its leading comments,
+/// or after the keyword of a `struct`, `union` or `enum` definition.
+pub fn mark_symbol(sym: &Symbol, marks: &[&str]) -> String {
+    if marks.is_empty() {
+        return sym.text.clone();
+    }
+
+    let mut pos = code_start(&sym.text);
+    if let Some(keyword) = match sym.kind {
+        SymbolKind::Struct => Some("struct"),
+        SymbolKind::Union => Some("union"),
+        SymbolKind::Enum => Some("enum"),
+        _ => None,
+    } {
+        if sym.text[pos..].starts_with(keyword) {
+            pos += keyword.len();
+            return format!(
+                "{} {}{}",
+                &sym.text[..pos],
+                marks.join(" "),
+                &sym.text[pos..]
+            );
+        }
+    }
+
+    format!(
+        "{}{} {}",
+        &sym.text[..pos],
+        marks.join(" "),
+        &sym.text[pos..]
+    )
+}
+
+/// Returns the offset of the first character that is not white space or a comment
+fn code_start(text: &str) -> usize {
+    let mut pos = 0;
+
+    loop {
+        let rest = &text[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+
+        if let Some(comment) = trimmed.strip_prefix("/*") {
+            pos += comment.find("*/").map_or(trimmed.len(), |end| end + 4);
+        } else if trimmed.starts_with("//") {
+            pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else {
+            return pos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::SyncTest;
+    use crate::SyncError;
+
+    #[test]
+    fn test_mark_symbols() {
+        let test = SyncTest::new(
+            "mark_symbols",
+            "#define ROM_MAX 4\n/** init */\nvoid rom_init(void);\n\
+             struct rom_cfg {\n    int a;\n};\nextern int rom_count;\n",
+        );
+        let mut syncer = test.syncer();
+        syncer.set_mark_symbols(vec![
+            "rom_*=ROM_API",
+            "/^ROM_/=weak",
+            "rom_init=deprecated",
+            "rom_count=section:.rom_data",
+            "unknown=weak",
+        ]);
+        syncer.run().unwrap();
+
+        let res = test.target();
+        assert!(res.contains("#define ROM_MAX 4\n"));
+        assert!(
+            res.contains("/** init */\nROM_API __attribute__((deprecated)) void rom_init(void);\n")
+        );
+        assert!(res.contains("struct ROM_API rom_cfg {\n"));
+        assert!(
+            res.contains("ROM_API __attribute__((section(\".rom_data\"))) extern int rom_count;\n")
+        );
+
+        syncer.set_mark_symbols(vec!["rom_init"]);
+        assert!(matches!(syncer.run(), Err(SyncError::Mark { .. })));
+    }
+}
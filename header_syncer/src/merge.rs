@@ -0,0 +1,192 @@
+use crate::{split_symbols, SymbolKind};
+
+/// Merges a newly generated block into the block a To file already has.
+///
+/// Symbols are matched by kind and name, other parts (comments, `#if` lines) by their text.
+/// Symbols still generated keep their place in `old` and take the new text if it changed
+/// other than in white space, symbols no longer generated are deleted and new symbols
+/// are inserted after the last entry of their section.
+///
+/// `banners` are the comment lines heading the block and its sections,
+/// they are matched on their own rather than as part of the symbol below them.
+pub fn merge_block(old: &[&str], new: &[String], banners: &[String]) -> Vec<String> {
+    let new: Vec<&str> = new.iter().flat_map(|l| l.split('\n')).collect();
+    let old_entries = entries(old, banners);
+    let mut new_entries: Vec<Option<Entry>> =
+        entries(&new, banners).into_iter().map(Some).collect();
+    // the banner of the section of each new entry
+    let new_sections = sections(new_entries.iter().flatten(), banners);
+
+    let mut merged: Vec<Entry> = Vec::new();
+    for entry in old_entries {
+        let found = new_entries
+            .iter_mut()
+            .find(|n| n.as_ref().is_some_and(|n| n.key == entry.key));
+        if let Some(found) = found {
+            let found = found.take().unwrap();
+            if same_tokens(&found.text, &entry.text) {
+                merged.push(entry);
+            } else {
+                merged.push(Entry {
+                    text: found.text,
+                    ..entry
+                });
+            }
+        }
+    }
+    for (entry, section) in new_entries.into_iter().zip(new_sections) {
+        if let Some(entry) = entry {
+            let end = sections(&merged, banners)
+                .iter()
+                .rposition(|s| *s == section)
+                .map_or(merged.len(), |i| i + 1);
+            merged.insert(end, entry);
+        }
+    }
+
+    let mut lines = Vec::new();
+    for entry in merged {
+        if entry.blank_before && !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(entry.text.lines().map(|l| l.to_string()));
+    }
+
+    lines
+}
+
+struct Entry {
+    key: (SymbolKind, String),
+    text: String,
+    blank_before: bool,
+}
+
+fn entries(lines: &[&str], banners: &[String]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut prev_end = 0;
+
+    for sym in split_symbols(lines, 1) {
+        let mut blank_before = prev_end != 0 && sym.line > prev_end + 1;
+        prev_end = sym.line + sym.text.lines().count() - 1;
+
+        let mut text = sym.text.as_str();
+        while let Some((line, rest)) = text.split_once('\n') {
+            if !banners.iter().any(|b| b == line) {
+                break;
+            }
+            entries.push(Entry {
+                key: (SymbolKind::Other, line.to_string()),
+                text: line.to_string(),
+                blank_before,
+            });
+            blank_before = false;
+            text = rest;
+        }
+
+        let key = if sym.name.is_empty() {
+            (sym.kind, text.trim().to_string())
+        } else {
+            (sym.kind, sym.name)
+        };
+        entries.push(Entry {
+            key,
+            text: text.to_string(),
+            blank_before,
+        });
+    }
+
+    entries
+}
+
+/// Returns the banner heading the section of each entry, `None` before the first banner
+fn sections<'a>(
+    entries: impl IntoIterator<Item = &'a Entry>,
+    banners: &[String],
+) -> Vec<Option<String>> {
+    let mut section = None;
+    entries
+        .into_iter()
+        .map(|entry| {
+            if entry.key.0 == SymbolKind::Other && banners.contains(&entry.key.1) {
+                section = Some(entry.key.1.clone());
+            }
+            section.clone()
+        })
+        .collect()
+}
+
+fn same_tokens(a: &str, b: &str) -> bool {
+    a.split_whitespace().eq(b.split_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merge_block;
+
+    #[test]
+    fn test_incremental() {
+        let old = vec![
+            "// test1.h: header-sync",
+            "void b(void);",
+            "",
+            "/* kept as is */",
+            "int  a;",
+            "void gone(void);",
+        ];
+        let new: Vec<String> = vec![
+            "// test1.h: header-sync\n/* kept as is */\nint a;".to_string(),
+            "void b(int x);".to_string(),
+            String::new(),
+            "void c(void);".to_string(),
+        ];
+
+        let banners = vec!["// test1.h: header-sync".to_string()];
+        assert_eq!(
+            merge_block(&old, &new, &banners),
+            vec![
+                "// test1.h: header-sync",
+                "void b(int x);",
+                "",
+                "/* kept as is */",
+                "int  a;",
+                "",
+                "void c(void);",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_sections() {
+        let old = vec![
+            "// test1.h: header-sync",
+            "void a1(void);",
+            "// test2.h: header-sync",
+            "void b1(void);",
+        ];
+        let new: Vec<String> = vec![
+            "// test1.h: header-sync\nvoid a1(void);".to_string(),
+            "void a2(void);".to_string(),
+            "// test2.h: header-sync\nvoid b1(void);".to_string(),
+            "void b2(void);".to_string(),
+            "// test3.h: header-sync\nvoid c1(void);".to_string(),
+        ];
+
+        let banners: Vec<String> = ["test1.h", "test2.h", "test3.h"]
+            .iter()
+            .map(|f| format!("// {}: header-sync", f))
+            .collect();
+        assert_eq!(
+            merge_block(&old, &new, &banners),
+            vec![
+                "// test1.h: header-sync",
+                "void a1(void);",
+                "void a2(void);",
+                "// test2.h: header-sync",
+                "void b1(void);",
+                "void b2(void);",
+                "// test3.h: header-sync",
+                "void c1(void);",
+            ]
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use regex::Regex;
+
+/// Matches symbol names, the rule is one of
+/// + an exact name, e.g. `func1`
+/// + a glob with `*` and `?`, e.g. `test1_*`
+/// + a regex between slashes, e.g. `/^test[0-9]+$/`
+#[derive(Debug, Clone)]
+pub struct SymbolPattern {
+    rule: String,
+    regex: Option<Regex>,
+}
+
+impl SymbolPattern {
+    pub fn new(rule: &str) -> Result<SymbolPattern, regex::Error> {
+        let regex = if rule.len() > 1 && rule.starts_with('/') && rule.ends_with('/') {
+            Some(Regex::new(&rule[1..rule.len() - 1])?)
+        } else if rule.contains(['*', '?']) {
+            let glob: String = rule
+                .chars()
+                .map(|c| match c {
+                    '*' => ".*".to_string(),
+                    '?' => ".".to_string(),
+                    c => regex::escape(&c.to_string()),
+                })
+                .collect();
+            Some(Regex::new(&format!("^{}$", glob))?)
+        } else {
+            None
+        };
+
+        Ok(SymbolPattern {
+            rule: rule.to_string(),
+            regex,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.rule
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(name),
+            None => self.rule == name,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use similar::{ChangeTag, TextDiff};
+
+use crate::TextFormat;
+
+/// The content of a To file before and after syncing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetChange {
+    pub path: String,
+    pub old: String,
+    pub new: String,
+    /// Names of the autogen blocks whose content changed, `""` for the unnamed one
+    pub regions: Vec<String>,
+    /// How the To file is stored, `old` and `new` are decoded with `\n` line endings
+    pub format: TextFormat,
+}
+
+impl TargetChange {
+    pub fn is_changed(&self) -> bool {
+        self.old != self.new
+    }
+
+    /// Returns the change as a unified diff, empty if nothing changed
+    pub fn unified_diff(&self) -> String {
+        if !self.is_changed() {
+            return String::new();
+        }
+
+        TextDiff::from_lines(&self.old, &self.new)
+            .unified_diff()
+            .context_radius(3)
+            .header(&self.path, &self.path)
+            .to_string()
+    }
+
+    /// Returns the number of lines added and removed
+    pub fn line_counts(&self) -> (usize, usize) {
+        let diff = TextDiff::from_lines(&self.old, &self.new);
+        let count = |tag| diff.iter_all_changes().filter(|c| c.tag() == tag).count();
+
+        (count(ChangeTag::Insert), count(ChangeTag::Delete))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{example, TestDir, LABEL};
+    use crate::Syncer;
+
+    #[test]
+    fn test_plan() {
+        let dir = TestDir::new("plan");
+        let old = "// api\n/* header-sync autogen start */\nvoid old(void);\n/* header-sync autogen end */\n";
+        let to = dir.write("api.h", old);
+
+        let mut syncer = Syncer::new(vec![&example("test1.h")], vec![to.to_str().unwrap()], LABEL);
+        let changes = syncer.plan().unwrap();
+
+        assert_eq!(fs::read_to_string(&to).unwrap(), old);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_changed());
+        assert_eq!(changes[0].regions, [""]);
+        assert!(changes[0]
+            .new
+            .starts_with("// api\n/* header-sync autogen start */\n// test1.h"));
+        let diff = changes[0].unified_diff();
+        assert!(diff.contains("\n-void old(void);\n"));
+        assert!(diff.contains("\n+extern void func2(void);\n"));
+    }
+}
@@ -0,0 +1,891 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use encoding_rs::Encoding;
+
+use crate::symbol::remove_comments;
+use crate::text::TextFormat;
+
+/// What is done with the `#if` blocks of a sync region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionalMode {
+    /// Keep the lines of the active branches only, without the `#if` lines
+    Evaluate,
+    /// Keep the `#if` lines, but drop the lines of the inactive branches
+    Prune,
+}
+
+/// Evaluates the `#if` blocks of a header the way the C preprocessor would with the
+/// given `-D`, `-U` and `-I` options.
+///
+/// Macros are defined by the options, the header, and the headers it includes that
+/// are found in the directory of the header or the include paths. The options take
+/// precedence over the `#define`s and `#undef`s of the headers.
+///
+/// An `#if` that can't be evaluated, e.g. one calling a function-like macro that isn't
+/// defined, keeps all its branches and its `#if` lines, with a warning. In the `ast`
+/// parse mode the options are given to the C preprocessor, which evaluates the `#if`s.
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    mode: ConditionalMode,
+    /// `Some(value)` for `-D`, `None` for `-U`
+    options: Vec<(String, Option<String>)>,
+    include_paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    /// The parameters of a function-like macro
+    params: Option<Vec<String>>,
+    body: String,
+    /// Defined or undefined in a branch that can't be evaluated
+    uncertain: bool,
+}
+
+/// Whether a branch of an `#if` block is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Branch {
+    Taken,
+    Skipped,
+    /// The `#if` can't be evaluated
+    Unknown,
+}
+
+impl Branch {
+    fn and(self, other: Branch) -> Branch {
+        match (self, other) {
+            (Branch::Skipped, _) | (_, Branch::Skipped) => Branch::Skipped,
+            (Branch::Unknown, _) | (_, Branch::Unknown) => Branch::Unknown,
+            _ => Branch::Taken,
+        }
+    }
+
+    fn or(self, other: Branch) -> Branch {
+        match (self, other) {
+            (Branch::Taken, _) | (_, Branch::Taken) => Branch::Taken,
+            (Branch::Unknown, _) | (_, Branch::Unknown) => Branch::Unknown,
+            _ => Branch::Skipped,
+        }
+    }
+}
+
+/// A `#if` block being scanned
+struct Frame {
+    /// Whether the lines around the block are kept
+    outer: Branch,
+    /// Whether the current branch is taken
+    branch: Branch,
+    /// Whether the current or an earlier branch is taken
+    taken: Branch,
+    /// Whether a branch of the block can't be evaluated
+    unknown: bool,
+    /// The first and last lines of the `#if`, `#elif` and `#else` lines so far
+    directives: Vec<(usize, usize)>,
+}
+
+impl Preprocessor {
+    pub fn new(mode: ConditionalMode) -> Preprocessor {
+        Preprocessor {
+            mode,
+            options: Vec::new(),
+            include_paths: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> ConditionalMode {
+        self.mode
+    }
+
+    /// Defines a macro like `-D`, e.g. `CONFIG_UART` or `CONFIG_UART_NUM=2`
+    pub fn define(&mut self, def: &str) {
+        let (name, value) = def.split_once('=').unwrap_or((def, "1"));
+        self.set_option(name, Some(value.to_string()));
+    }
+
+    /// Undefines a macro like `-U`
+    pub fn undefine(&mut self, name: &str) {
+        self.set_option(name, None);
+    }
+
+    /// Adds a directory to search included headers in, like `-I`
+    pub fn include_path(&mut self, dir: &str) {
+        self.include_paths.push(PathBuf::from(dir));
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<String>) {
+        self.options.retain(|(n, _)| n != name);
+        self.options.push((name.to_string(), value));
+    }
+
+    /// Returns the options for the command line of a C preprocessor
+    pub fn cpp_options(&self) -> Vec<String> {
+        let mut options: Vec<String> = self
+            .options
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("-D{}={}", name, value),
+                None => format!("-U{}", name),
+            })
+            .collect();
+        options.extend(
+            self.include_paths
+                .iter()
+                .map(|dir| format!("-I{}", dir.display())),
+        );
+        options
+    }
+
+    /// Returns for each line of a header whether it is kept, and a warning for each
+    /// `#if` of the header that can't be evaluated.
+    /// The included headers are decoded as UTF-8 or else as `encoding`.
+    pub(crate) fn kept_lines(
+        &self,
+        path: &str,
+        text: &str,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<(Vec<bool>, Vec<String>), String> {
+        let mut macros = HashMap::new();
+        for (name, value) in &self.options {
+            if let Some(value) = value {
+                macros.insert(
+                    name.clone(),
+                    Macro {
+                        params: None,
+                        body: value.clone(),
+                        uncertain: false,
+                    },
+                );
+            }
+        }
+
+        self.scan(Path::new(path), text, encoding, false, &mut macros, 0)
+    }
+
+    /// Scans a header, `uncertain` if it is included in a branch that can't be evaluated
+    fn scan(
+        &self,
+        path: &Path,
+        text: &str,
+        encoding: Option<&'static Encoding>,
+        uncertain: bool,
+        macros: &mut HashMap<String, Macro>,
+        depth: usize,
+    ) -> Result<(Vec<bool>, Vec<String>), String> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut kept = vec![false; lines.len()];
+        let mut warnings = Vec::new();
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut in_comment = false;
+        let start = if uncertain {
+            Branch::Unknown
+        } else {
+            Branch::Taken
+        };
+        let mut i = 0;
+
+        while i < lines.len() {
+            let first = i;
+            let was_in_comment = in_comment;
+            let mut code = remove_comments(lines[i], &mut in_comment);
+            while lines[i].trim_end().ends_with('\\') && i + 1 < lines.len() {
+                i += 1;
+                code.pop();
+                code.push_str(&remove_comments(lines[i], &mut in_comment));
+            }
+            let err = |message: String| format!("{}:{}: {}", path.display(), first + 1, message);
+            let mut branch = |result: Result<bool, String>| match result {
+                Ok(true) => Branch::Taken,
+                Ok(false) => Branch::Skipped,
+                Err(message) => {
+                    warnings.push(format!(
+                        "{}:{}: {}, all the branches are kept",
+                        path.display(),
+                        first + 1,
+                        message
+                    ));
+                    Branch::Unknown
+                }
+            };
+
+            let state = stack.last().map_or(start, |f| f.outer.and(f.branch));
+            let mut keep = state != Branch::Skipped;
+            let directive = match code.trim_start().strip_prefix('#') {
+                Some(directive) if !was_in_comment => directive.trim_start(),
+                _ => "",
+            };
+            let (name, rest) = directive
+                .split_once(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or((directive, ""));
+            let rest = rest.trim();
+            // the frame of a conditional line
+            let mut frame = None;
+
+            match name {
+                "if" | "ifdef" | "ifndef" => {
+                    let cond = match state {
+                        Branch::Skipped => Branch::Skipped,
+                        _ => branch(self.condition(path, name, rest, macros)),
+                    };
+                    stack.push(Frame {
+                        outer: state,
+                        branch: cond,
+                        taken: cond,
+                        unknown: cond == Branch::Unknown,
+                        directives: vec![(first, i)],
+                    });
+                    frame = stack.last();
+                }
+                "elif" => {
+                    let f = stack
+                        .last_mut()
+                        .ok_or_else(|| err("#elif without #if".to_string()))?;
+                    let cond = match (f.outer, f.taken) {
+                        (Branch::Skipped, _) | (_, Branch::Taken) => Branch::Skipped,
+                        _ => branch(self.condition(path, name, rest, macros)),
+                    };
+                    f.branch = match (f.taken, cond) {
+                        (_, Branch::Skipped) => Branch::Skipped,
+                        (Branch::Skipped, cond) => cond,
+                        _ => Branch::Unknown,
+                    };
+                    f.taken = f.taken.or(f.branch);
+                    f.unknown |= f.branch == Branch::Unknown;
+                    f.directives.push((first, i));
+                    frame = stack.last();
+                }
+                "else" => {
+                    let f = stack
+                        .last_mut()
+                        .ok_or_else(|| err("#else without #if".to_string()))?;
+                    f.branch = match f.taken {
+                        Branch::Taken => Branch::Skipped,
+                        Branch::Skipped => Branch::Taken,
+                        Branch::Unknown => Branch::Unknown,
+                    };
+                    f.taken = Branch::Taken;
+                    f.unknown |= f.branch == Branch::Unknown;
+                    f.directives.push((first, i));
+                    frame = stack.last();
+                }
+                "endif" => {
+                    let f = stack
+                        .pop()
+                        .ok_or_else(|| err("#endif without #if".to_string()))?;
+                    // the block is written with all its conditional lines, or none of them
+                    if f.unknown && f.outer != Branch::Skipped {
+                        for (start, end) in &f.directives {
+                            for k in &mut kept[*start..=*end] {
+                                *k = true;
+                            }
+                        }
+                    }
+                    keep = f.outer != Branch::Skipped
+                        && (self.mode == ConditionalMode::Prune
+                            || f.outer == Branch::Unknown
+                            || f.unknown);
+                }
+                "define" if state != Branch::Skipped => {
+                    let (name, body) = rest
+                        .split_once(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                        .map_or((rest, ""), |(name, _)| (name, &rest[name.len()..]));
+                    let (params, body) = match body.strip_prefix('(') {
+                        Some(body) => {
+                            let (params, body) = body
+                                .split_once(')')
+                                .ok_or_else(|| err(format!("bad parameters of {}", name)))?;
+                            let params = params
+                                .split(',')
+                                .map(|p| p.trim().to_string())
+                                .filter(|p| !p.is_empty())
+                                .collect();
+                            (Some(params), body)
+                        }
+                        None => (None, body),
+                    };
+                    if !self.options.iter().any(|(n, _)| n == name) {
+                        macros.insert(
+                            name.to_string(),
+                            Macro {
+                                params,
+                                body: body.trim().to_string(),
+                                uncertain: state == Branch::Unknown,
+                            },
+                        );
+                    }
+                }
+                "undef"
+                    if state != Branch::Skipped && !self.options.iter().any(|(n, _)| n == rest) =>
+                {
+                    if state == Branch::Taken {
+                        macros.remove(rest);
+                    } else {
+                        macros.insert(
+                            rest.to_string(),
+                            Macro {
+                                params: None,
+                                body: String::new(),
+                                uncertain: true,
+                            },
+                        );
+                    }
+                }
+                "include" if state != Branch::Skipped && depth < 32 => {
+                    if let Some(include) = self.find_include(path, lines[first]) {
+                        if let Ok(data) = fs::read(&include) {
+                            let (text, _) = TextFormat::decode(&data, encoding)
+                                .map_err(|message| format!("{}: {}", include.display(), message))?;
+                            // only the macros of an included header matter
+                            self.scan(
+                                &include,
+                                &text,
+                                encoding,
+                                state == Branch::Unknown,
+                                macros,
+                                depth + 1,
+                            )?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(f) = frame {
+                keep = f.outer != Branch::Skipped
+                    && (self.mode == ConditionalMode::Prune
+                        || f.outer == Branch::Unknown
+                        || f.unknown);
+            }
+            for k in &mut kept[first..=i] {
+                *k = keep;
+            }
+            i += 1;
+        }
+
+        if !stack.is_empty() {
+            return Err(format!("{}: #if without #endif", path.display()));
+        }
+        Ok((kept, warnings))
+    }
+
+    /// Evaluates the condition of an `#if`, `#ifdef`, `#ifndef` or `#elif` line
+    fn condition(
+        &self,
+        path: &Path,
+        directive: &str,
+        expr: &str,
+        macros: &HashMap<String, Macro>,
+    ) -> Result<bool, String> {
+        let value = match directive {
+            "ifdef" => is_defined(expr, macros),
+            "ifndef" => is_defined(expr, macros).map(|defined| !defined),
+            _ => self
+                .replace_has_include(path, expr)
+                .and_then(|expr| eval(&expr, macros))
+                .map(|value| value != 0),
+        };
+        value.map_err(|message| format!("#{} {} can't be evaluated ({})", directive, expr, message))
+    }
+
+    /// Replaces each `__has_include(<header>)` or `__has_include("header")` by 1 or 0
+    fn replace_has_include(&self, path: &Path, expr: &str) -> Result<String, String> {
+        let mut replaced = String::new();
+        let mut rest = expr;
+
+        while let Some(pos) = rest.find("__has_include") {
+            let (before, after) = rest.split_at(pos);
+            let name_len = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            let name = &after[..name_len];
+            replaced.push_str(before);
+            rest = &after[name_len..];
+
+            let is_ident = before
+                .chars()
+                .last()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+            let in_defined = replaced
+                .trim_end()
+                .trim_end_matches('(')
+                .trim_end()
+                .ends_with("defined");
+            if is_ident || in_defined || !matches!(name, "__has_include" | "__has_include_next") {
+                replaced.push_str(name);
+                continue;
+            }
+
+            let args = rest.trim_start();
+            let header = args
+                .strip_prefix('(')
+                .and_then(|args| args.split_once(')'))
+                .ok_or_else(|| format!("{} without a header", name))?;
+            let found = self.find_header(path, header.0.trim())?.is_some();
+            replaced.push_str(if found { "1" } else { "0" });
+            rest = header.1;
+        }
+
+        replaced.push_str(rest);
+        Ok(replaced)
+    }
+
+    /// Returns the path of the header named by an `#include` line
+    fn find_include(&self, path: &Path, line: &str) -> Option<PathBuf> {
+        let rest = line.trim_start().strip_prefix('#')?.trim_start();
+        let rest = rest.strip_prefix("include")?.trim();
+
+        self.find_header(path, rest).ok().flatten()
+    }
+
+    /// Returns the path of a header given as `"name"` or `<name>`, if it is found
+    fn find_header(&self, path: &Path, header: &str) -> Result<Option<PathBuf>, String> {
+        let (name, quoted) = if let Some(rest) = header.strip_prefix('"') {
+            (rest.split_once('"').map(|(name, _)| name), true)
+        } else if let Some(rest) = header.strip_prefix('<') {
+            (rest.split_once('>').map(|(name, _)| name), false)
+        } else {
+            (None, false)
+        };
+        let name = name.ok_or_else(|| format!("{} is not a header name", header))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Ok(quoted
+            .then(|| dir.to_path_buf())
+            .into_iter()
+            .chain(self.include_paths.iter().cloned())
+            .map(|dir| dir.join(name))
+            .find(|p| p.is_file()))
+    }
+}
+
+/// Tells whether a macro is defined
+fn is_defined(name: &str, macros: &HashMap<String, Macro>) -> Result<bool, String> {
+    match macros.get(name) {
+        Some(m) if m.uncertain => Err(format!(
+            "{} is defined in a branch that can't be evaluated",
+            name
+        )),
+        Some(_) => Ok(true),
+        None => Ok(matches!(name, "__has_include" | "__has_include_next")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Punct(&'static str),
+}
+
+const PUNCTS: [&str; 27] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&", "|",
+    "^", "!", "~", "?", ":", "(", ")", ",", "#", ".",
+];
+
+/// Evaluates the expression of an `#if` line
+fn eval(expr: &str, macros: &HashMap<String, Macro>) -> Result<i64, String> {
+    let tokens = expand(&tokenize(expr)?, macros, 0)?;
+    let mut pos = 0;
+    let value = eval_ternary(&tokens, &mut pos)?;
+
+    match tokens.get(pos) {
+        None => Ok(value),
+        Some(token) => Err(format!("unexpected {:?} in #if {}", token, expr)),
+    }
+}
+
+/// Replaces `defined` and the macros by their values, undefined macros are 0
+fn expand(
+    tokens: &[Token],
+    macros: &HashMap<String, Macro>,
+    depth: usize,
+) -> Result<Vec<Token>, String> {
+    if depth > 32 {
+        return Err("macros nest too deep".to_string());
+    }
+
+    let mut expanded = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Ident(name) if name == "defined" => {
+                let name = match (tokens.get(i + 1), tokens.get(i + 2), tokens.get(i + 3)) {
+                    (
+                        Some(Token::Punct("(")),
+                        Some(Token::Ident(name)),
+                        Some(Token::Punct(")")),
+                    ) => {
+                        i += 4;
+                        name
+                    }
+                    (Some(Token::Ident(name)), _, _) => {
+                        i += 2;
+                        name
+                    }
+                    _ => return Err("defined without a macro name".to_string()),
+                };
+                expanded.push(Token::Number(is_defined(name, macros)? as i64));
+                continue;
+            }
+            Token::Ident(name) => {
+                let call = tokens.get(i + 1) == Some(&Token::Punct("("));
+                match macros.get(name) {
+                    Some(m) if m.uncertain => {
+                        return Err(format!(
+                            "{} is defined in a branch that can't be evaluated",
+                            name
+                        ));
+                    }
+                    Some(Macro {
+                        params: Some(params),
+                        body,
+                        ..
+                    }) if call => {
+                        let (args, end) = macro_args(tokens, i + 1)?;
+                        let mut replaced = Vec::new();
+                        for token in tokenize(body)? {
+                            match &token {
+                                Token::Punct("#") => {
+                                    return Err(format!("# in macro {}", name));
+                                }
+                                Token::Ident(param) if param == "__VA_ARGS__" => {
+                                    let first = params.len().saturating_sub(1);
+                                    for (k, arg) in args.iter().enumerate().skip(first) {
+                                        if k > first {
+                                            replaced.push(Token::Punct(","));
+                                        }
+                                        replaced.extend(arg.iter().cloned());
+                                    }
+                                }
+                                Token::Ident(param) => {
+                                    match params.iter().position(|p| p == param) {
+                                        Some(k) => replaced
+                                            .extend(args.get(k).into_iter().flatten().cloned()),
+                                        None => replaced.push(token.clone()),
+                                    }
+                                }
+                                _ => replaced.push(token.clone()),
+                            }
+                        }
+                        expanded.extend(expand(&replaced, macros, depth + 1)?);
+                        i = end;
+                        continue;
+                    }
+                    // a function-like macro without arguments is not expanded
+                    Some(Macro {
+                        params: Some(_), ..
+                    }) => expanded.push(Token::Number(0)),
+                    Some(m) => {
+                        let body = tokenize(&m.body)?;
+                        expanded.extend(expand(&body, macros, depth + 1)?);
+                    }
+                    None if call => {
+                        return Err(format!("function-like macro {} is not defined", name));
+                    }
+                    None => expanded.push(Token::Number(0)),
+                }
+            }
+            token => expanded.push(token.clone()),
+        }
+        i += 1;
+    }
+
+    Ok(expanded)
+}
+
+/// Returns the arguments of a macro call starting with the `(` at `open`,
+/// and the position after the `)`
+fn macro_args(tokens: &[Token], open: usize) -> Result<(Vec<Vec<Token>>, usize), String> {
+    let mut args = vec![Vec::new()];
+    let mut nesting = 0;
+
+    for (i, token) in tokens.iter().enumerate().skip(open + 1) {
+        match token {
+            Token::Punct(")") if nesting == 0 => return Ok((args, i + 1)),
+            Token::Punct(",") if nesting == 0 => args.push(Vec::new()),
+            _ => {
+                match token {
+                    Token::Punct("(") => nesting += 1,
+                    Token::Punct(")") => nesting -= 1,
+                    _ => {}
+                }
+                args.last_mut().unwrap().push(token.clone());
+            }
+        }
+    }
+
+    Err("unterminated macro call".to_string())
+}
+
+fn eval_ternary(tokens: &[Token], pos: &mut usize) -> Result<i64, String> {
+    let cond = eval_binary(tokens, pos, 1)?;
+    if tokens.get(*pos) != Some(&Token::Punct("?")) {
+        return Ok(cond);
+    }
+
+    *pos += 1;
+    let a = eval_ternary(tokens, pos)?;
+    if tokens.get(*pos) != Some(&Token::Punct(":")) {
+        return Err("expected : in #if".to_string());
+    }
+    *pos += 1;
+    let b = eval_ternary(tokens, pos)?;
+
+    Ok(if cond != 0 { a } else { b })
+}
+
+fn eval_binary(tokens: &[Token], pos: &mut usize, min_prec: u8) -> Result<i64, String> {
+    let mut lhs = eval_unary(tokens, pos)?;
+
+    while let Some(Token::Punct(op)) = tokens.get(*pos) {
+        let prec = match *op {
+            "*" | "/" | "%" => 10,
+            "+" | "-" => 9,
+            "<<" | ">>" => 8,
+            "<" | ">" | "<=" | ">=" => 7,
+            "==" | "!=" => 6,
+            "&" => 5,
+            "^" => 4,
+            "|" => 3,
+            "&&" => 2,
+            "||" => 1,
+            _ => break,
+        };
+        if prec < min_prec {
+            break;
+        }
+
+        *pos += 1;
+        let rhs = eval_binary(tokens, pos, prec + 1)?;
+        lhs = match *op {
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err("division by zero in #if".to_string()),
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "<" => (lhs < rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "&" => lhs & rhs,
+            "^" => lhs ^ rhs,
+            "|" => lhs | rhs,
+            "&&" => (lhs != 0 && rhs != 0) as i64,
+            _ => (lhs != 0 || rhs != 0) as i64,
+        };
+    }
+
+    Ok(lhs)
+}
+
+fn eval_unary(tokens: &[Token], pos: &mut usize) -> Result<i64, String> {
+    let token = tokens
+        .get(*pos)
+        .ok_or("unexpected end of #if".to_string())?;
+    *pos += 1;
+
+    match token {
+        Token::Number(n) => Ok(*n),
+        Token::Punct("(") => {
+            let value = eval_ternary(tokens, pos)?;
+            if tokens.get(*pos) != Some(&Token::Punct(")")) {
+                return Err("expected ) in #if".to_string());
+            }
+            *pos += 1;
+            Ok(value)
+        }
+        Token::Punct("!") => Ok((eval_unary(tokens, pos)? == 0) as i64),
+        Token::Punct("~") => Ok(!eval_unary(tokens, pos)?),
+        Token::Punct("-") => Ok(eval_unary(tokens, pos)?.wrapping_neg()),
+        Token::Punct("+") => eval_unary(tokens, pos),
+        token => Err(format!("unexpected {:?} in #if", token)),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let bytes = expr.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let number = expr[start..i].trim_end_matches(['u', 'U', 'l', 'L']);
+            let value = if let Some(hex) = number
+                .strip_prefix("0x")
+                .or_else(|| number.strip_prefix("0X"))
+            {
+                i64::from_str_radix(hex, 16)
+            } else if number.len() > 1 && number.starts_with('0') {
+                i64::from_str_radix(&number[1..], 8)
+            } else {
+                number.parse()
+            };
+            tokens.push(Token::Number(
+                value.map_err(|_| format!("bad number {} in #if", &expr[start..i]))?,
+            ));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(expr[start..i].to_string()));
+        } else if c == b'\'' {
+            let end = expr[i + 1..]
+                .find('\'')
+                .ok_or("unterminated character in #if".to_string())?;
+            let ch = &expr[i + 1..i + 1 + end];
+            let value = match ch {
+                "\\0" => 0,
+                "\\n" => 10,
+                "\\t" => 9,
+                _ => ch.chars().last().map_or(0, |c| c as i64),
+            };
+            tokens.push(Token::Number(value));
+            i += end + 2;
+        } else if let Some(p) = PUNCTS.iter().find(|p| expr[i..].starts_with(**p)) {
+            tokens.push(Token::Punct(p));
+            i += p.len();
+        } else {
+            return Err(format!("unexpected {} in #if", &expr[i..]));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{sync_region, SyncTest, EMPTY_BLOCK};
+    use crate::{ConditionalMode, ParseMode, Preprocessor, SyncError};
+
+    #[test]
+    fn test_preprocess() {
+        let test = SyncTest::new("preprocess", "");
+        fs::create_dir_all(test.dir.join("inc")).unwrap();
+        test.dir.write("inc/config.h", "#define UART_NUM 2\n");
+        fs::write(
+            &test.from,
+            format!(
+                "#include <config.h>\n{}",
+                sync_region(
+                    "#if UART_NUM > 1 && defined(USE_UART)\nvoid uart1_init(void);\n\
+                     #elif UART_NUM\nvoid uart0_init(void);\n#else\nvoid no_uart(void);\n\
+                     #endif\n\n#ifndef NO_GPIO\nvoid gpio_init(void);\n#endif\n"
+                )
+            ),
+        )
+        .unwrap();
+
+        let mut pp = Preprocessor::new(ConditionalMode::Evaluate);
+        pp.define("USE_UART");
+        pp.include_path(test.dir.join("inc").to_str().unwrap());
+        let mut syncer = test.syncer();
+        syncer.set_preprocessor(pp);
+        syncer.run().unwrap();
+        let res = test.target();
+        assert!(res.contains("void uart1_init(void);\n\nvoid gpio_init(void);\n"));
+        assert!(!res.contains("uart0_init") && !res.contains("#if"));
+
+        let mut pp = Preprocessor::new(ConditionalMode::Prune);
+        pp.define("NO_GPIO");
+        syncer.set_preprocessor(pp);
+        fs::write(&test.to, EMPTY_BLOCK).unwrap();
+        syncer.run().unwrap();
+        assert!(test.target().contains(
+            "#if UART_NUM > 1 && defined(USE_UART)\n#elif UART_NUM\n#else\nvoid no_uart(void);\n\
+             #endif\n\n#ifndef NO_GPIO\n#endif\n"
+        ));
+
+        // an `#if` that can't be evaluated keeps all its branches
+        let mut pp = Preprocessor::new(ConditionalMode::Evaluate);
+        pp.define("UART_NUM=1 +");
+        syncer.set_preprocessor(pp.clone());
+        fs::write(&test.to, EMPTY_BLOCK).unwrap();
+        syncer.run().unwrap();
+        assert!(test.target().contains(
+            "#if UART_NUM > 1 && defined(USE_UART)\nvoid uart1_init(void);\n\
+             #elif UART_NUM\nvoid uart0_init(void);\n#else\nvoid no_uart(void);\n#endif\n"
+        ));
+        assert_eq!(syncer.warnings().len(), 2);
+        assert!(syncer.warnings()[0].contains("test1.h:3: #if UART_NUM > 1"));
+
+        fs::write(&test.from, sync_region("#if 1\nvoid a(void);\n")).unwrap();
+        assert!(matches!(syncer.run(), Err(SyncError::Preprocess { .. })));
+    }
+
+    #[test]
+    fn test_preprocess_macros() {
+        let test = SyncTest::new("preprocess_macros", "");
+        test.dir.write("config.h", "#define CONFIG_UART 1\n");
+        // an included header in GBK, "串口" in a comment
+        test.dir.write(
+            "board.h",
+            b"/* \xb4\xae\xbf\xda */\n#define BOARD_UARTS(n) ((n) * 2)\n".as_slice(),
+        );
+        fs::write(
+            &test.from,
+            format!(
+                "#include \"board.h\"\n#define IS_ENABLED(x) (x + 0)\n{}",
+                sync_region(
+                    "#if IS_ENABLED(CONFIG_UART) && BOARD_UARTS(1) == 2\nvoid uart_init(void);\n\
+                     #endif\n#if __has_include(\"config.h\") && !__has_include(<none.h>)\n\
+                     void config_init(void);\n#endif\n#if __GNUC_PREREQ(4, 6)\n\
+                     void gnu_init(void);\n#else\nvoid other_init(void);\n#endif\n\
+                     #if 0\nvoid a(void);\n#elif __has_builtin(__builtin_expect)\n\
+                     void b(void);\n#endif\n"
+                )
+            ),
+        )
+        .unwrap();
+
+        let mut pp = Preprocessor::new(ConditionalMode::Evaluate);
+        pp.define("CONFIG_UART");
+        let mut syncer = test.syncer();
+        syncer.set_encoding("gbk").unwrap();
+        syncer.set_preprocessor(pp);
+        syncer.run().unwrap();
+        let res = test.target();
+        assert!(res.contains("void uart_init(void);\nvoid config_init(void);\n"));
+        assert!(res.contains(
+            "#if __GNUC_PREREQ(4, 6)\nvoid gnu_init(void);\n#else\nvoid other_init(void);\n\
+             #endif\n#if 0\n#elif __has_builtin(__builtin_expect)\nvoid b(void);\n#endif\n"
+        ));
+        assert!(!res.contains("void a(void)"));
+        assert_eq!(syncer.warnings().len(), 2);
+        assert!(syncer.warnings()[0].contains("function-like macro __GNUC_PREREQ is not defined"));
+    }
+
+    #[test]
+    fn test_preprocess_ast() {
+        let test = SyncTest::new(
+            "preprocess_ast",
+            "#if defined(USE_UART)\n#define UART_NUM 2\nvoid uart_init(void);\n#else\n\
+             #define UART_NUM 0\n#endif\n",
+        );
+        let mut pp = Preprocessor::new(ConditionalMode::Evaluate);
+        pp.define("USE_UART");
+        let mut syncer = test.syncer();
+        syncer.set_parse_mode(ParseMode::Ast);
+        syncer.set_preprocessor(pp);
+        syncer.run().unwrap();
+        let res = test.target();
+        assert!(res.contains("#define UART_NUM 2\n"));
+        assert!(res.contains("void uart_init(void);\n"));
+        assert!(!res.contains("UART_NUM 0"));
+    }
+}
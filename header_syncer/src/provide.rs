@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use crate::{compress_declaration, ElfFile, MapFile, Symbol, SymbolKind};
+
+/// Addresses of symbols for `PROVIDE` scripts, read from an ELF file or archive,
+/// a GNU ld map file, or a CSV file of `<name>,<address>` lines
+#[derive(Debug, Default)]
+pub struct SymbolAddresses {
+    addresses: HashMap<String, u64>,
+}
+
+impl SymbolAddresses {
+    /// Parses the content of an address source, the format is told by its content
+    pub fn parse(data: &[u8]) -> Result<SymbolAddresses, String> {
+        if data.starts_with(b"\x7fELF") || data.starts_with(b"!<arch>\n") {
+            let elf = ElfFile::parse(data)?;
+            return Ok(SymbolAddresses {
+                addresses: elf
+                    .symbols
+                    .into_iter()
+                    .map(|s| (s.name, s.address))
+                    .collect(),
+            });
+        }
+
+        let text = std::str::from_utf8(data).map_err(|_| "not an ELF, map or CSV file")?;
+        if text.contains("Linker script and memory map") {
+            let map = MapFile::parse(text);
+            return Ok(SymbolAddresses {
+                addresses: map
+                    .symbols
+                    .into_iter()
+                    .map(|s| (s.name, s.address))
+                    .collect(),
+            });
+        }
+
+        SymbolAddresses::parse_csv(text)
+    }
+
+    /// Parses `<name>,<address>` lines, the address is hex with `0x` or decimal.
+    /// Blank lines, `#` comments and a header line are skipped.
+    pub fn parse_csv(text: &str) -> Result<SymbolAddresses, String> {
+        let mut addresses = HashMap::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, address) = line
+                .split_once(',')
+                .map(|(name, address)| (name.trim().trim_matches('"'), address.trim()))
+                .ok_or(format!("line {}: expected <name>,<address>", i + 1))?;
+            let address = match address.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => address.parse().ok(),
+            };
+            match address {
+                Some(address) => {
+                    addresses.insert(name.to_string(), address);
+                }
+                None if addresses.is_empty() && i == 0 => {}
+                None => return Err(format!("line {}: the address is not a number", i + 1)),
+            }
+        }
+
+        Ok(SymbolAddresses { addresses })
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.addresses.get(name).copied()
+    }
+
+    /// Returns whether a `PROVIDE` is written for the symbol, for functions
+    /// without a body and variables
+    pub fn is_provided(sym: &Symbol) -> bool {
+        match sym.kind {
+            SymbolKind::Function => !compress_declaration(&sym.text).contains('{'),
+            SymbolKind::Variable => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the `PROVIDE` line of a symbol, or a comment flagging it if it has no address
+    pub fn provide_line(&self, name: &str) -> String {
+        match self.get(name) {
+            Some(address) => format!("PROVIDE({} = 0x{:08x});", name, address),
+            None => format!("/* {}: no address */", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{example, TestDir, EMPTY_BLOCK, LABEL};
+    use crate::{SyncError, Syncer};
+
+    #[test]
+    fn test_provide() {
+        let dir = TestDir::new("provide");
+        let from = dir.write(
+            "rom.h",
+            "/* header-sync start */\n#define ROM_MAX 4\nint rom_add(int a, int b);\n\
+             extern int rom_version;\nvoid rom_missing(void);\n/* header-sync end */\n",
+        );
+        let csv = dir.write(
+            "rom.csv",
+            "name,address\nrom_add,0x1000\nrom_version,536870912\n",
+        );
+        let header = dir.join("api.h");
+        let script = dir.join("rom.ld");
+
+        for source in [
+            example("rom.elf"),
+            example("rom.map"),
+            csv.to_string_lossy().to_string(),
+        ] {
+            for to in [&header, &script] {
+                fs::write(to, EMPTY_BLOCK).unwrap();
+            }
+            let mut syncer = Syncer::new(
+                vec![from.to_str().unwrap()],
+                vec![header.to_str().unwrap(), script.to_str().unwrap()],
+                LABEL,
+            );
+            syncer.set_provide_addresses(&source);
+            syncer.run().unwrap();
+
+            let address = if source.ends_with(".elf") {
+                0x11a0
+            } else {
+                0x2000_0000
+            };
+            assert_eq!(
+                fs::read_to_string(&script).unwrap(),
+                format!(
+                    "/* header-sync autogen start */\n/* rom.h: header-sync */\n\
+                     PROVIDE(rom_add = 0x00001000);\nPROVIDE(rom_version = 0x{:08x});\n\
+                     /* rom_missing: no address */\n/* header-sync autogen end */\n",
+                    address
+                )
+            );
+            assert!(fs::read_to_string(&header)
+                .unwrap()
+                .contains("#define ROM_MAX 4\nint rom_add(int a, int b);\n"));
+            assert_eq!(syncer.missing_addresses(), ["rom_missing"]);
+            assert!(syncer.sources().contains(&source));
+        }
+
+        fs::write(&csv, "rom_add,0x1000\nrom_version,none\n").unwrap();
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![script.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_provide_addresses(csv.to_str().unwrap());
+        assert!(matches!(syncer.run(), Err(SyncError::Addresses { .. })));
+    }
+}
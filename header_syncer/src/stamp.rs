@@ -0,0 +1,138 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::format_utc;
+
+/// Provenance of a generated block, written as its first line, e.g.
+/// `// header-sync: header_syncer 0.1.0, from test1.h, input fnv1a64 af63bd4c8601b7df, generated 20231114-221320 UTC`
+pub struct Stamp {
+    /// File names of the From files of the block, so the stamp is the same from any working directory
+    pub sources: Vec<String>,
+    /// Hash of the regions the block is generated from, the address source
+    /// and the settings that change the output
+    pub input_hash: u64,
+}
+
+impl Stamp {
+    /// Returns whether `line` is a stamp written with `prefix`
+    pub fn is_stamp(prefix: &str, line: &str) -> bool {
+        line.starts_with(&format!("{} header_syncer ", prefix))
+    }
+
+    /// Returns the stamp line, `old` is kept if only its time differs.
+    /// A `/*` prefix is closed by ` */` at the end of the line.
+    pub fn line(&self, prefix: &str, old: Option<&str>) -> String {
+        let end = if prefix.starts_with("/*") { " */" } else { "" };
+        let head = format!(
+            "{} header_syncer {}, from {}, input fnv1a64 {:016x}",
+            prefix,
+            env!("CARGO_PKG_VERSION"),
+            self.sources.join(" "),
+            self.input_hash
+        );
+
+        if let Some(old) = old {
+            if old
+                .rsplit_once(", generated ")
+                .is_some_and(|(h, time)| h == head && time.ends_with(end))
+            {
+                return old.to_string();
+            }
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        format!("{}, generated {} UTC{}", head, format_utc(time), end)
+    }
+}
+
+/// Continues a 64-bit FNV-1a hash with `data`, start with [`FNV1A64_INIT`]
+pub fn fnv1a64(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+pub const FNV1A64_INIT: u64 = 0xcbf2_9ce4_8422_2325;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{autogen_block, SyncTest, LABEL};
+    use crate::Syncer;
+
+    /// Returns the stamp in the second line of a To file without its time
+    fn stamp_head(text: &str) -> &str {
+        let stamp = text.lines().nth(1).unwrap();
+        stamp.rsplit_once(", generated ").unwrap().0
+    }
+
+    #[test]
+    fn test_stamp() {
+        let test = SyncTest::new("stamp", "void v1(void);\n");
+        let mut syncer = test.syncer();
+        syncer.set_stamp(true);
+        syncer.run().unwrap();
+        let res = test.target();
+        let stamp = res.lines().nth(1).unwrap();
+        assert!(stamp.starts_with("// header-sync: header_syncer 0.1.0, from test1.h, input "));
+        assert!(stamp.ends_with(" UTC"));
+        assert!(!syncer.plan().unwrap()[0].is_changed());
+
+        test.set_code("void v2(void);\n");
+        let changes = syncer.plan().unwrap();
+        assert_ne!(stamp_head(&res), stamp_head(&changes[0].new));
+
+        syncer.set_ignore_symbols(vec!["v3"]);
+        let ignored = syncer.plan().unwrap();
+        assert_ne!(stamp_head(&changes[0].new), stamp_head(&ignored[0].new));
+    }
+
+    #[test]
+    fn test_stamp_regions() {
+        let test = SyncTest::new("stamp_regions", "");
+        let region = |name: &str, code: &str| {
+            format!(
+                "/* header-sync:{} start */\n{}/* header-sync:{} end */\n",
+                name, code, name
+            )
+        };
+        let uart = test
+            .dir
+            .write("uart.h", region("uart", "void uart_init(void);\n"));
+        fs::write(&test.from, region("gpio", "void gpio_init(void);\n")).unwrap();
+        let blocks = format!("{}{}", autogen_block("gpio", ""), autogen_block("uart", ""));
+        fs::write(&test.to, &blocks).unwrap();
+        let script = test.dir.write("api.ld", &blocks);
+        let mut syncer = Syncer::new(
+            vec![&test.from, uart.to_str().unwrap()],
+            vec![&test.to, script.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_stamp(true);
+        syncer.run().unwrap();
+
+        let stamps = |text: &str| -> Vec<String> {
+            text.lines()
+                .filter(|l| l.contains(": header_syncer "))
+                .map(|l| l.to_string())
+                .collect()
+        };
+        let header = stamps(&test.target());
+        assert!(header[0].starts_with("// header-sync: header_syncer 0.1.0, from test1.h, "));
+        assert!(header[1].starts_with("// header-sync: header_syncer 0.1.0, from uart.h, "));
+        let ld = stamps(&fs::read_to_string(&script).unwrap());
+        assert!(ld[0].starts_with("/* header-sync: header_syncer 0.1.0, from test1.h, "));
+        assert!(ld[0].ends_with(" UTC */"));
+        assert!(syncer.plan().unwrap().iter().all(|c| !c.is_changed()));
+
+        fs::write(&uart, region("uart", "void uart_init(int baud);\n")).unwrap();
+        let changes = syncer.plan().unwrap();
+        for (change, old) in changes.iter().zip([header, ld]) {
+            let new = stamps(&change.new);
+            assert_eq!(new[0], old[0]);
+            assert_ne!(new[1], old[1]);
+        }
+    }
+}
@@ -0,0 +1,731 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process::Command;
+
+use lang_c::ast::{
+    Declaration, DeclarationSpecifier, Declarator, DeclaratorKind, DerivedDeclarator,
+    ExternalDeclaration, StorageClassSpecifier, StructKind, TypeSpecifier,
+};
+use lang_c::driver::{parse, Config, Error};
+use lang_c::loc::get_location_for_offset;
+
+/// Kinds of declarations, in the order they usually appear in a header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SymbolKind {
+    Macro,
+    Typedef,
+    Enum,
+    Struct,
+    Union,
+    Variable,
+    Function,
+    /// Anything that is not a declaration, e.g. `#if` lines, kept as written
+    Other,
+}
+
+/// A single declaration found in a sync region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// First line of `text` in the source file, 1-based
+    pub line: usize,
+    /// The declaration as written in the source, including its leading comments
+    pub text: String,
+}
+
+/// Parses the declarations on the given lines (1-based) of a header.
+///
+/// The header is run through the preprocessor of `config` and lang-c, then each
+/// top-level declaration is mapped back to the original source text, with one symbol
+/// for each of its declarators. `#define`s are taken from the source directly.
+///
+/// Also returns the lines of the declarations without a name and of the directives
+/// other than `#define` and conditionals, which are not synced.
+pub fn parse_symbols(
+    config: &Config,
+    path: &str,
+    lines: Range<usize>,
+) -> Result<(Vec<Symbol>, Vec<usize>), Error> {
+    let source = fs::read_to_string(path).map_err(Error::PreprocessorError)?;
+    parse_symbols_text(config, path, &source, lines)
+}
+
+/// Same as [`parse_symbols`] with the text of the header already read, e.g. decoded from GBK
+pub fn parse_symbols_text(
+    config: &Config,
+    path: &str,
+    source: &str,
+    lines: Range<usize>,
+) -> Result<(Vec<Symbol>, Vec<usize>), Error> {
+    let source_lines: Vec<&str> = source.lines().collect();
+    let parsed = parse(config, path)?;
+
+    // (first line, last line, name, kind)
+    let mut found = Vec::new();
+    let mut skipped = Vec::new();
+    let mut source_file = SourceFile::new(path);
+
+    for ext in &parsed.unit.0 {
+        let (start, includes) = get_location_for_offset(&parsed.source, ext.span.start);
+        if !includes.is_empty() || !source_file.is(start.file) || !lines.contains(&start.line) {
+            continue;
+        }
+        let (end, _) = get_location_for_offset(&parsed.source, ext.span.end.saturating_sub(1));
+
+        let names = match &ext.node {
+            ExternalDeclaration::Declaration(decl) => declaration_names(&decl.node),
+            ExternalDeclaration::FunctionDefinition(def) => {
+                declarator_name(&def.node.declarator.node)
+                    .map(|n| (n, SymbolKind::Function))
+                    .into_iter()
+                    .collect()
+            }
+            ExternalDeclaration::StaticAssert(_) => Vec::new(),
+        };
+        if names.is_empty() {
+            skipped.push(start.line);
+        }
+        for (name, kind) in names {
+            found.push((start.line, end.line.max(start.line), name, kind));
+        }
+    }
+
+    let mut line = lines.start;
+    while line < lines.end {
+        let text = source_lines.get(line - 1).map_or("", |l| l.trim_start());
+        let first = line;
+        while source_lines
+            .get(line - 1)
+            .is_some_and(|l| l.trim_end().ends_with('\\'))
+        {
+            line += 1;
+        }
+        if let Some(name) = macro_name(text) {
+            found.push((first, line, name, SymbolKind::Macro));
+        } else if text.starts_with('#') && !is_conditional(text) {
+            skipped.push(first);
+        }
+        line += 1;
+    }
+
+    found.sort_by_key(|f| f.0);
+    skipped.sort_unstable();
+
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut prev_start = 0;
+    let mut prev_end = lines.start - 1;
+    for (start, end, name, kind) in found {
+        // another declarator of the same declaration, e.g. `b` of `int a, b;`
+        if start == prev_start {
+            if let Some(prev) = symbols.last() {
+                symbols.push(Symbol {
+                    name,
+                    kind,
+                    ..prev.clone()
+                });
+                continue;
+            }
+        }
+        prev_start = start;
+
+        let mut first = start;
+        while first - 1 > prev_end && is_comment(source_lines[first - 2]) {
+            first -= 1;
+        }
+        let text = source_lines[first - 1..end.min(source_lines.len())]
+            .iter()
+            .map(|l| l.trim_end())
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        symbols.push(Symbol {
+            name,
+            kind,
+            line: first,
+            text,
+        });
+        prev_end = end;
+    }
+
+    Ok((symbols, skipped))
+}
+
+/// Returns the lines of the directives of a header the preprocessor of `config` keeps,
+/// i.e. the `#define`s, `#undef`s, `#include`s and `#pragma`s outside the `#if` branches
+/// it skips, as printed with `-dD -dI`.
+pub fn active_directives(config: &Config, path: &str) -> Result<HashSet<usize>, Error> {
+    let output = Command::new(&config.cpp_command)
+        .args(&config.cpp_options)
+        .args(["-dD", "-dI"])
+        .arg(path)
+        .output()
+        .map_err(Error::PreprocessorError)?;
+    if !output.status.success() {
+        return Err(Error::PreprocessorError(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )));
+    }
+
+    let mut source_file = SourceFile::new(path);
+    let mut in_source = false;
+    let mut line = 0;
+    let mut directives = HashSet::new();
+    for out in String::from_utf8_lossy(&output.stdout).lines() {
+        // a linemarker, e.g. `# 12 "a.h" 2`, names the file and line of the next line
+        let marker = out.strip_prefix("# ").and_then(|rest| {
+            let (number, file) = rest.split_once(' ')?;
+            let file = file.strip_prefix('"')?;
+            Some((number.parse().ok()?, &file[..file.rfind('"')?]))
+        });
+        if let Some((number, file)) = marker {
+            in_source = source_file.is(file);
+            line = number;
+            continue;
+        }
+        if in_source && out.starts_with('#') {
+            directives.insert(line);
+        }
+        line += 1;
+    }
+
+    Ok(directives)
+}
+
+/// Tells whether a file named by the preprocessor is the header being parsed,
+/// the linemarkers may name it by another path, e.g. `./a.h` or with `\\`
+struct SourceFile<'a> {
+    path: &'a str,
+    canonical: Option<PathBuf>,
+    files: HashMap<String, bool>,
+}
+
+impl SourceFile<'_> {
+    fn new(path: &str) -> SourceFile<'_> {
+        SourceFile {
+            path,
+            canonical: fs::canonicalize(path).ok(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn is(&mut self, file: &str) -> bool {
+        *self
+            .files
+            .entry(file.replace("\\\\", "\\"))
+            .or_insert_with_key(|file| {
+                file == self.path
+                    || self.canonical.is_some() && fs::canonicalize(file).ok() == self.canonical
+            })
+    }
+}
+
+/// Splits the lines of a sync region into declarations without parsing them.
+///
+/// `first_line` is the line number (1-based) of `lines[0]` in the source file.
+/// Every line except blank lines between declarations ends up in exactly one symbol,
+/// so writing the symbols back reproduces the region.
+pub fn split_symbols(lines: &[&str], first_line: usize) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut code = String::new();
+    let mut depth = 0;
+    let mut in_comment = false;
+    let mut i = 0;
+
+    let push = |symbols: &mut Vec<Symbol>, first: usize, last: usize, code: &str| {
+        let name_and_kind = if code.trim_start().starts_with('#') {
+            macro_name(code.trim_start()).map(|name| (name, SymbolKind::Macro))
+        } else {
+            statement_name(code)
+        };
+        let (name, kind) = name_and_kind.unwrap_or((String::new(), SymbolKind::Other));
+        symbols.push(Symbol {
+            name,
+            kind,
+            line: first_line + first,
+            text: lines[first..=last]
+                .iter()
+                .map(|l| l.trim_end())
+                .collect::<Vec<&str>>()
+                .join("\n"),
+        });
+    };
+
+    while i < lines.len() {
+        let line = lines[i];
+        let was_in_comment = in_comment;
+        let line_code = strip_comments(line, &mut in_comment);
+
+        if code.is_empty() && depth == 0 {
+            let trimmed = line_code.trim();
+
+            if line.trim().is_empty() && !was_in_comment {
+                if let Some(first) = start.take() {
+                    push(&mut symbols, first, i - 1, "");
+                }
+                i += 1;
+                continue;
+            }
+            if trimmed.starts_with('#') {
+                let first = start.take().unwrap_or(i);
+                let mut directive = line_code.clone();
+                while lines[i].trim_end().ends_with('\\') && i + 1 < lines.len() {
+                    i += 1;
+                    directive.push_str(&strip_comments(lines[i], &mut in_comment));
+                }
+                push(&mut symbols, first, i, &directive);
+                i += 1;
+                continue;
+            }
+            // comments are kept with the declaration that follows them
+            if trimmed.is_empty() {
+                start.get_or_insert(i);
+                i += 1;
+                continue;
+            }
+            // `extern "C" {` and its closing brace
+            if trimmed == "}" || (line.trim().starts_with("extern \"C\"") && trimmed.ends_with('{'))
+            {
+                let first = start.take().unwrap_or(i);
+                push(&mut symbols, first, i, "");
+                i += 1;
+                continue;
+            }
+            start.get_or_insert(i);
+        }
+
+        let mut ended = false;
+        for c in line_code.chars() {
+            code.push(c);
+            match c {
+                '{' | '(' | '[' => depth += 1,
+                ')' | ']' => depth -= 1,
+                '}' => {
+                    depth -= 1;
+                    // end of a function definition
+                    if depth == 0
+                        && code
+                            .find('{')
+                            .is_some_and(|p| code[..p].trim_end().ends_with(')'))
+                    {
+                        ended = true;
+                    }
+                }
+                ';' if depth == 0 => ended = true,
+                _ => {}
+            }
+        }
+        code.push('\n');
+
+        if ended {
+            push(&mut symbols, start.take().unwrap_or(i), i, &code);
+            code.clear();
+            depth = 0;
+        }
+        i += 1;
+    }
+
+    if let Some(first) = start {
+        push(&mut symbols, first, lines.len() - 1, "");
+    }
+
+    symbols
+}
+
+/// Returns `line` with comments and string contents removed
+pub(crate) fn strip_comments(line: &str, in_comment: &mut bool) -> String {
+    strip(line, in_comment, false)
+}
+
+/// Returns `line` with comments removed, but not the strings, e.g. of `__has_include("a.h")`
+pub(crate) fn remove_comments(line: &str, in_comment: &mut bool) -> String {
+    strip(line, in_comment, true)
+}
+
+fn strip(line: &str, in_comment: &mut bool, keep_strings: bool) -> String {
+    let mut code = String::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if *in_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                *in_comment = false;
+                code.push(' ');
+            }
+        } else if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            *in_comment = true;
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            break;
+        } else if c == '"' || c == '\'' {
+            code.push(c);
+            while let Some(s) = chars.next() {
+                if s == c {
+                    break;
+                }
+                if keep_strings {
+                    code.push(s);
+                }
+                if s == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        if keep_strings {
+                            code.push(escaped);
+                        }
+                    }
+                }
+            }
+            code.push(c);
+        } else {
+            code.push(c);
+        }
+    }
+
+    code
+}
+
+/// Guesses the name and kind of a declaration from its tokens
+fn statement_name(code: &str) -> Option<(String, SymbolKind)> {
+    const QUALIFIERS: [&str; 11] = [
+        "typedef", "extern", "static", "inline", "const", "volatile", "register", "restrict",
+        "struct", "union", "enum",
+    ];
+
+    let tokens = tokenize_code(code);
+    let is_ident = |t: &str| {
+        t.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && !QUALIFIERS.contains(&t)
+    };
+
+    // tokens outside of any `{ ... }`, up to the initializer or the end
+    let mut head = Vec::new();
+    let mut body_ident = None;
+    let mut braces = 0;
+    for t in &tokens {
+        match t.as_str() {
+            "{" => braces += 1,
+            "}" => braces -= 1,
+            _ if braces == 0 => head.push(t.as_str()),
+            _ if body_ident.is_none() && is_ident(t) => body_ident = Some(t.as_str()),
+            _ => {}
+        }
+    }
+    if let Some(end) = head.iter().position(|t| *t == ";" || *t == "=") {
+        head.truncate(end);
+    }
+
+    let mut last_ident = None;
+    let mut parens = 0;
+    for t in &head {
+        match *t {
+            "(" | "[" => parens += 1,
+            ")" | "]" => parens -= 1,
+            _ if parens == 0 && is_ident(t) => last_ident = Some(*t),
+            _ => {}
+        }
+    }
+    // `(*name)` outside of a parameter list
+    let mut fn_pointer = None;
+    let mut depth = 0;
+    for (i, t) in head.iter().enumerate() {
+        match *t {
+            "(" if depth == 0 && head.get(i + 1) == Some(&"*") => {
+                fn_pointer = head.get(i + 2).copied().filter(|t| is_ident(t));
+                if fn_pointer.is_some() {
+                    break;
+                }
+                depth += 1;
+            }
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ => {}
+        }
+    }
+
+    if head.contains(&"typedef") {
+        return fn_pointer
+            .or(last_ident)
+            .map(|name| (name.to_string(), SymbolKind::Typedef));
+    }
+
+    if let Some(k) = head
+        .iter()
+        .position(|t| matches!(*t, "struct" | "union" | "enum"))
+    {
+        let tag = head.get(k + 1).copied().filter(|t| is_ident(t));
+        if last_ident.is_none() || last_ident == tag {
+            let kind = match head[k] {
+                "struct" => SymbolKind::Struct,
+                "union" => SymbolKind::Union,
+                _ => SymbolKind::Enum,
+            };
+            let name = match kind {
+                SymbolKind::Enum => tag.or(body_ident),
+                _ => tag,
+            };
+            return name.map(|name| (name.to_string(), kind));
+        }
+    }
+
+    if let Some(p) = head.iter().position(|t| *t == "(") {
+        if let Some(name) = fn_pointer {
+            return Some((name.to_string(), SymbolKind::Variable));
+        }
+        if p > 0 && is_ident(head[p - 1]) {
+            return Some((head[p - 1].to_string(), SymbolKind::Function));
+        }
+    }
+
+    last_ident.map(|name| (name.to_string(), SymbolKind::Variable))
+}
+
+/// Splits code into identifiers, numbers and single punctuation characters,
+/// dropping compiler extensions such as `__attribute__((...))`
+pub(crate) fn tokenize_code(code: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut chars = code.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let mut token = c.to_string();
+            while let Some(&n) = chars.peek() {
+                if !n.is_ascii_alphanumeric() && n != '_' {
+                    break;
+                }
+                token.push(n);
+                chars.next();
+            }
+            tokens.push(token);
+        } else if !c.is_whitespace() {
+            tokens.push(c.to_string());
+        }
+    }
+
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let is_extension = matches!(
+            tokens[i].as_str(),
+            "__attribute__" | "__attribute" | "__declspec" | "__asm__" | "__asm" | "asm"
+        );
+        if is_extension && tokens.get(i + 1).map(|t| t.as_str()) == Some("(") {
+            let mut depth = 0;
+            i += 1;
+            while i < tokens.len() {
+                match tokens[i].as_str() {
+                    "(" => depth += 1,
+                    ")" => depth -= 1,
+                    _ => {}
+                }
+                i += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            continue;
+        }
+        res.push(tokens[i].clone());
+        i += 1;
+    }
+
+    res
+}
+
+fn is_comment(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("//") || line.starts_with("/*") || line.starts_with('*')
+}
+
+fn macro_name(line: &str) -> Option<String> {
+    let rest = line
+        .strip_prefix('#')?
+        .trim_start()
+        .strip_prefix("define")?;
+    let name: String = rest
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Returns whether the line is an `#if`, `#ifdef`, `#ifndef`, `#elif`, `#else` or `#endif`,
+/// which the preprocessor has already applied
+fn is_conditional(line: &str) -> bool {
+    let directive = line.trim_start_matches('#').trim_start();
+    ["if", "elif", "else", "endif"]
+        .iter()
+        .any(|d| directive.starts_with(d))
+}
+
+/// Returns the name and kind of each declarator, or of the tag a bare declaration defines
+fn declaration_names(decl: &Declaration) -> Vec<(String, SymbolKind)> {
+    let is_typedef = decl.specifiers.iter().any(|s| {
+        matches!(
+            &s.node,
+            DeclarationSpecifier::StorageClass(sc) if sc.node == StorageClassSpecifier::Typedef
+        )
+    });
+
+    if !decl.declarators.is_empty() {
+        return decl
+            .declarators
+            .iter()
+            .filter_map(|init| {
+                let declarator = &init.node.declarator.node;
+                let kind = if is_typedef {
+                    SymbolKind::Typedef
+                } else if is_function(declarator) {
+                    SymbolKind::Function
+                } else {
+                    SymbolKind::Variable
+                };
+                declarator_name(declarator).map(|name| (name, kind))
+            })
+            .collect();
+    }
+
+    // a bare `struct x { ... };` or `enum { ... };`
+    decl.specifiers
+        .iter()
+        .find_map(|s| match &s.node {
+            DeclarationSpecifier::TypeSpecifier(ts) => match &ts.node {
+                TypeSpecifier::Struct(st) => {
+                    let kind = match st.node.kind.node {
+                        StructKind::Struct => SymbolKind::Struct,
+                        StructKind::Union => SymbolKind::Union,
+                    };
+                    st.node
+                        .identifier
+                        .as_ref()
+                        .map(|id| (id.node.name.clone(), kind))
+                }
+                TypeSpecifier::Enum(en) => en
+                    .node
+                    .identifier
+                    .as_ref()
+                    .or_else(|| en.node.enumerators.first().map(|e| &e.node.identifier))
+                    .map(|id| (id.node.name.clone(), SymbolKind::Enum)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .into_iter()
+        .collect()
+}
+
+fn declarator_name(declarator: &Declarator) -> Option<String> {
+    match &declarator.kind.node {
+        DeclaratorKind::Identifier(id) => Some(id.node.name.clone()),
+        DeclaratorKind::Declarator(inner) => declarator_name(&inner.node),
+        DeclaratorKind::Abstract => None,
+    }
+}
+
+fn is_function(declarator: &Declarator) -> bool {
+    match &declarator.kind.node {
+        DeclaratorKind::Identifier(_) => declarator.derived.iter().any(|d| {
+            matches!(
+                d.node,
+                DerivedDeclarator::Function(_) | DerivedDeclarator::KRFunction(_)
+            )
+        }),
+        DeclaratorKind::Declarator(inner) => is_function(&inner.node),
+        DeclaratorKind::Abstract => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lang_c::driver::Config;
+
+    use crate::test_util::{example, SyncTest, LABEL};
+    use crate::{parse_symbols, split_symbols, ParseMode, SymbolKind, Syncer};
+
+    #[test]
+    fn test_parse_symbols() {
+        let path = example("test2.h");
+        let syncer = Syncer::new(vec![&path], vec![], LABEL);
+        let (_, lines) = syncer
+            .get_label_lines(&fs::read_to_string(&path).unwrap())
+            .remove(0);
+        let (symbols, skipped) = parse_symbols(&Config::default(), &path, lines.clone()).unwrap();
+        assert!(skipped.is_empty());
+
+        let names: Vec<(&str, SymbolKind)> =
+            symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("TEST2_MAX", SymbolKind::Macro),
+                ("TEST2_SUM", SymbolKind::Macro),
+                ("test2_id_t", SymbolKind::Typedef),
+                ("test2_cfg_t", SymbolKind::Typedef),
+                ("test2_value", SymbolKind::Union),
+                ("test2_state", SymbolKind::Enum),
+                ("test2_count", SymbolKind::Variable),
+                ("test2_cb_t", SymbolKind::Typedef),
+                ("test2_init", SymbolKind::Function),
+            ]
+        );
+        assert!(symbols[3]
+            .text
+            .starts_with("/** Configuration of test2 */\ntypedef struct"));
+        assert!(symbols[8].text.ends_with("               test2_cb_t cb);"));
+
+        let text = fs::read_to_string(&path).unwrap();
+        let region: Vec<&str> = text
+            .lines()
+            .skip(lines.start - 1)
+            .take(lines.len())
+            .collect();
+        let split = split_symbols(&region, lines.start);
+        let split_names: Vec<(&str, SymbolKind)> =
+            split.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(split_names, names);
+        assert_eq!(split[3].text, symbols[3].text);
+    }
+
+    #[test]
+    fn test_parse_declarators() {
+        let test = SyncTest::new(
+            "declarators",
+            "#pragma pack(1)\n/* a and b */\nint a, *b;\nstruct { int x; };\n\
+             #ifdef X\n#endif\n",
+        );
+        let lines = 2..8;
+        let (symbols, skipped) = parse_symbols(&Config::default(), &test.from, lines).unwrap();
+        let names: Vec<(&str, usize)> = symbols.iter().map(|s| (s.name.as_str(), s.line)).collect();
+        assert_eq!(names, [("a", 3), ("b", 3)]);
+        assert_eq!(symbols[1].text, "/* a and b */\nint a, *b;");
+        assert_eq!(skipped, [2, 5]);
+
+        let mut syncer = test.syncer();
+        syncer.set_parse_mode(ParseMode::Ast);
+        syncer.set_ignore_symbols(vec!["a"]);
+        syncer.run().unwrap();
+        assert!(test
+            .target()
+            .contains("// test1.h: header-sync\n/* a and b */\nint a, *b;\n/* header-sync"));
+        assert_eq!(
+            syncer.warnings(),
+            [
+                format!("{}:2: #pragma pack(1) is not synced in AST mode", test.from),
+                format!(
+                    "{}:5: struct {{ int x; }}; is not synced in AST mode",
+                    test.from
+                ),
+            ]
+        );
+    }
+}
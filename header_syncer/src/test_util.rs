@@ -0,0 +1,116 @@
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::Syncer;
+
+/// The sync label of the tests
+pub(crate) const LABEL: &str = "/* header-sync */";
+
+/// A To file with an empty autogen block
+pub(crate) const EMPTY_BLOCK: &str =
+    "/* header-sync autogen start */\n/* header-sync autogen end */\n";
+
+/// Returns the path of a file in `examples/`
+pub(crate) fn example(name: &str) -> String {
+    format!("{}/examples/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Returns a From header with `code` between the sync labels
+pub(crate) fn sync_region(code: &str) -> String {
+    format!("/* header-sync start */\n{}/* header-sync end */\n", code)
+}
+
+/// Returns an autogen block holding `lines`, named `region` unless it is empty
+pub(crate) fn autogen_block(region: &str, lines: &str) -> String {
+    let words = crate::autogen_words(region, "");
+    format!(
+        "/* header-sync {}start */\n{}/* header-sync {}end */\n",
+        words, lines, words
+    )
+}
+
+/// A directory of its own for a test, removed at the end of the test
+pub(crate) struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!(
+            "header_syncer_test_{}_{}_{}",
+            name,
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+
+    /// Writes a file in the directory and returns its path
+    pub fn write(&self, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A sync from the header `test1.h` into the To file `api.h` in a [`TestDir`],
+/// `api.h` starts with an empty autogen block
+pub(crate) struct SyncTest {
+    pub dir: TestDir,
+    pub from: String,
+    pub to: String,
+}
+
+impl SyncTest {
+    /// `code` is written between the sync labels of `test1.h`
+    pub fn new(name: &str, code: &str) -> SyncTest {
+        let dir = TestDir::new(name);
+        let from = dir.write("test1.h", sync_region(code));
+        let to = dir.write("api.h", EMPTY_BLOCK);
+
+        SyncTest {
+            dir,
+            from: from.to_string_lossy().to_string(),
+            to: to.to_string_lossy().to_string(),
+        }
+    }
+
+    /// Replaces the code between the sync labels of `test1.h`
+    pub fn set_code(&self, code: &str) {
+        fs::write(&self.from, sync_region(code)).unwrap();
+    }
+
+    /// Returns a syncer from `test1.h` into `api.h`
+    pub fn syncer(&self) -> Syncer {
+        Syncer::new(vec![&self.from], vec![&self.to], LABEL)
+    }
+
+    /// Returns the content of `api.h`
+    pub fn target(&self) -> String {
+        fs::read_to_string(&self.to).unwrap()
+    }
+}
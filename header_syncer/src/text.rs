@@ -0,0 +1,207 @@
+use std::fs;
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::SyncError;
+
+/// How a text file is stored: its encoding, byte order mark and line endings.
+///
+/// Files are decoded to text with `\n` line endings, the format writes them back the same way.
+/// The line endings are the same for the whole file: a file with mixed line endings is written
+/// back with the ones most of its lines have. The synced lines also lose their trailing
+/// white space, the lines outside the autogen blocks are kept as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextFormat {
+    encoding: &'static Encoding,
+    bom: bool,
+    crlf: bool,
+}
+
+impl Default for TextFormat {
+    fn default() -> TextFormat {
+        TextFormat {
+            encoding: UTF_8,
+            bom: false,
+            crlf: false,
+        }
+    }
+}
+
+impl TextFormat {
+    /// Decodes the content of a file as UTF-8, with or without a byte order mark,
+    /// or else as `fallback`. A file without a byte order mark that is pure ASCII is
+    /// taken to be in `fallback`, so the lines synced into it are written in that encoding.
+    /// The line endings are `\r\n` if most lines end so.
+    pub fn decode(
+        data: &[u8],
+        fallback: Option<&'static Encoding>,
+    ) -> Result<(String, TextFormat), String> {
+        let (bom, data) = match data.strip_prefix(b"\xef\xbb\xbf") {
+            Some(data) => (true, data),
+            None => (false, data),
+        };
+        let (text, encoding) = match (std::str::from_utf8(data), fallback) {
+            (Ok(text), Some(encoding))
+                if !bom && data.is_ascii() && encoding.is_ascii_compatible() =>
+            {
+                (text.to_string(), encoding)
+            }
+            (Ok(text), _) => (text.to_string(), UTF_8),
+            (Err(_), Some(encoding)) if !bom => encoding
+                .decode_without_bom_handling_and_without_replacement(data)
+                .map(|text| (text.into_owned(), encoding))
+                .ok_or(format!("it is not valid {}", encoding.name()))?,
+            (Err(e), _) => {
+                return Err(format!(
+                    "it is not UTF-8 ({}), set the encoding of the files",
+                    e
+                ))
+            }
+        };
+
+        let crlf = text.matches("\r\n").count() * 2 > text.matches('\n').count();
+        let text = if crlf {
+            text.replace("\r\n", "\n")
+        } else {
+            text
+        };
+        Ok((
+            text,
+            TextFormat {
+                encoding,
+                bom,
+                crlf,
+            },
+        ))
+    }
+
+    /// Encodes text with `\n` line endings in the format
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, String> {
+        let text = if self.crlf {
+            text.replace('\n', "\r\n")
+        } else {
+            text.to_string()
+        };
+        let (bytes, encoding, unmappable) = self.encoding.encode(&text);
+        if unmappable || encoding != self.encoding {
+            return Err(format!("it can't be written in {}", self.encoding.name()));
+        }
+
+        let mut data = Vec::new();
+        if self.bom {
+            data.extend_from_slice(b"\xef\xbb\xbf");
+        }
+        data.extend_from_slice(&bytes);
+        Ok(data)
+    }
+
+    /// Returns the name of the encoding, e.g. `UTF-8` or `GBK`
+    pub fn encoding(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    pub fn is_crlf(&self) -> bool {
+        self.crlf
+    }
+}
+
+/// Returns the encoding of a label such as `gbk`, `gb18030` or `shift_jis`
+pub(crate) fn find_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or(format!("unknown encoding {}", label))
+}
+
+/// Reads and decodes a text file, see [`TextFormat::decode`]
+pub(crate) fn read_text(
+    path: &str,
+    fallback: Option<&'static Encoding>,
+) -> Result<(String, TextFormat), SyncError> {
+    let data = fs::read(path).map_err(|e| SyncError::io(path, e))?;
+    TextFormat::decode(&data, fallback).map_err(|message| SyncError::Encoding {
+        path: path.to_string(),
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{TestDir, LABEL};
+    use crate::{ParseMode, SyncError, Syncer};
+
+    #[test]
+    fn test_encoding() {
+        let dir = TestDir::new("encoding");
+        let from = dir.join("uart.h");
+        let gbk = |text: &str| encoding_rs::GBK.encode(text).0.into_owned();
+        fs::write(
+            &from,
+            gbk(
+                "/* header-sync start */\r\n/* \u{521d}\u{59cb}\u{5316}\u{4e32}\u{53e3} */\r\n\
+                 void uart_init(void);\r\n/* header-sync end */\r\n",
+            ),
+        )
+        .unwrap();
+        let to = dir.join("api.h");
+        let utf8 = dir.join("api_utf8.h");
+        let to_text = "#include <stdint.h>  \r\n/* header-sync autogen start */\r\n\
+                       /* header-sync autogen end */\r\n// \u{7ed3}\u{675f}";
+        let synced = "#include <stdint.h>  \r\n/* header-sync autogen start */\r\n\
+                      // uart.h: header-sync\r\n/* \u{521d}\u{59cb}\u{5316}\u{4e32}\u{53e3} */\r\n\
+                      void uart_init(void);\r\n/* header-sync autogen end */\r\n// \u{7ed3}\u{675f}";
+
+        for parse_mode in [ParseMode::Text, ParseMode::Ast] {
+            fs::write(&to, gbk(to_text)).unwrap();
+            fs::write(&utf8, format!("\u{feff}{}", to_text.replace("\r\n", "\n"))).unwrap();
+            let mut syncer = Syncer::new(
+                vec![from.to_str().unwrap()],
+                vec![to.to_str().unwrap(), utf8.to_str().unwrap()],
+                LABEL,
+            );
+            syncer.set_parse_mode(parse_mode);
+            assert!(matches!(syncer.run(), Err(SyncError::Encoding { .. })));
+
+            syncer.set_encoding("gbk").unwrap();
+            let written = syncer.run().unwrap();
+            assert_eq!(written[0].format.encoding(), "GBK");
+            assert!(written[0].format.is_crlf());
+            assert_eq!(fs::read(&to).unwrap(), gbk(synced));
+            assert_eq!(
+                fs::read_to_string(&utf8).unwrap(),
+                format!("\u{feff}{}", synced.replace("\r\n", "\n"))
+            );
+        }
+        assert!(Syncer::new(vec![], vec![], "")
+            .set_encoding("none")
+            .is_err());
+
+        // a To file in pure ASCII is in the encoding that is set
+        let ascii_text = "/* header-sync autogen start */\r\n/* header-sync autogen end */\r\n";
+        fs::write(&to, ascii_text).unwrap();
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_encoding("gbk").unwrap();
+        let written = syncer.run().unwrap();
+        assert_eq!(written[0].format.encoding(), "GBK");
+        assert_eq!(
+            fs::read(&to).unwrap(),
+            gbk(&synced[synced.find("/*").unwrap()..synced.find("// \u{7ed3}").unwrap()])
+        );
+
+        fs::write(
+            &from,
+            "/* header-sync start */\n// \u{1f600}\n/* header-sync end */\n",
+        )
+        .unwrap();
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_encoding("gbk").unwrap();
+        assert!(matches!(syncer.run(), Err(SyncError::Encoding { .. })));
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::deps::code_tokens;
+use crate::symbol::strip_comments;
+use crate::{
+    compress_declaration, defined_types, split_symbols, used_types, Symbol, SymbolKind, TypeName,
+};
+
+/// Returns the group of a declaration kind, in the order the groups are written
+fn group(kind: SymbolKind) -> usize {
+    match kind {
+        SymbolKind::Other => 0,
+        SymbolKind::Macro => 1,
+        SymbolKind::Typedef => 2,
+        SymbolKind::Enum => 3,
+        SymbolKind::Struct | SymbolKind::Union => 4,
+        SymbolKind::Variable => 5,
+        SymbolKind::Function => 6,
+    }
+}
+
+/// Tidies the lines of an autogen block: the declarations are grouped by kind (macros,
+/// typedefs, enums, structs and unions, variables, functions) with a blank line between
+/// the groups, sorted within a group after the types they use and then by name, repeated
+/// ones are dropped, and white space is normalised. Comments that belong to no declaration
+/// are put first.
+///
+/// A type definition whose body uses a type defined in a later group goes to that group,
+/// e.g. a typedef of a struct holding a `struct z` goes after `struct z`.
+///
+/// Returns `None` if the block has `#if` blocks or other code that is not a declaration,
+/// as it can't be reordered.
+pub fn tidy_block(lines: &[&str]) -> Option<Vec<String>> {
+    let mut symbols = split_symbols(lines, 1);
+    for sym in &mut symbols {
+        if sym.kind == SymbolKind::Other && !compress_declaration(&sym.text).is_empty() {
+            return None;
+        }
+        sym.text = normalize(sym);
+    }
+
+    let mut tidied = Vec::new();
+    let mut written: Vec<String> = Vec::new();
+    let mut last_group = None;
+    for (group, sym) in sort(&symbols) {
+        let decl = compress_declaration(&sym.text);
+        if sym.kind != SymbolKind::Other && written.contains(&decl) {
+            continue;
+        }
+        written.push(decl);
+
+        if last_group.is_some_and(|g| g != group) {
+            tidied.push(String::new());
+        }
+        last_group = Some(group);
+        tidied.extend(sym.text.lines().map(|l| l.to_string()));
+    }
+
+    Some(tidied)
+}
+
+/// Returns the declarations with their groups in the order they are written: by group,
+/// then each after the types of its group it uses, then by name and by place in the block,
+/// so declarations of the same name keep their order, e.g. `struct a;` and its body
+fn sort(symbols: &[Symbol]) -> Vec<(usize, &Symbol)> {
+    let mut defs: HashMap<TypeName, usize> = HashMap::new();
+    for (i, sym) in symbols.iter().enumerate() {
+        for t in defined_types(sym) {
+            defs.entry(t).or_insert(i);
+        }
+    }
+    let typedefs: HashSet<&str> = defs
+        .keys()
+        .filter_map(|t| match t {
+            TypeName::Typedef(name) => Some(name.as_str()),
+            TypeName::Tag(..) => None,
+        })
+        .collect();
+    let deps: Vec<Vec<usize>> = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, sym)| {
+            used_types(sym, &typedefs)
+                .iter()
+                .filter_map(|t| defs.get(t).copied())
+                .filter(|d| *d != i)
+                .collect()
+        })
+        .collect();
+
+    // a body may hold the types it uses by value, so it can't come before their definitions
+    let mut groups: Vec<usize> = symbols.iter().map(|sym| group(sym.kind)).collect();
+    let mut moved = true;
+    while moved {
+        moved = false;
+        for (i, sym) in symbols.iter().enumerate() {
+            if !code_tokens(&sym.text).iter().any(|t| t == "{") {
+                continue;
+            }
+            for &d in &deps[i] {
+                if groups[d] > groups[i] {
+                    groups[i] = groups[d];
+                    moved = true;
+                }
+            }
+        }
+    }
+
+    let mut written = vec![false; symbols.len()];
+    let mut sorted = Vec::new();
+    while let Some(group) = (0..symbols.len())
+        .filter(|i| !written[*i])
+        .map(|i| groups[i])
+        .min()
+    {
+        let left: Vec<usize> = (0..symbols.len())
+            .filter(|i| !written[*i] && groups[*i] == group)
+            .collect();
+        let key = |i: &&usize| (&symbols[**i].name, **i);
+        // a cycle of types is broken at its first one
+        let next = left
+            .iter()
+            .filter(|i| deps[**i].iter().all(|d| written[*d] || groups[*d] != group))
+            .min_by_key(key)
+            .or_else(|| left.iter().min_by_key(key))
+            .copied()
+            .unwrap_or_default();
+        written[next] = true;
+        sorted.push((group, &symbols[next]));
+    }
+
+    sorted
+}
+
+/// Returns the text of a declaration with trailing white space removed, indenting tabs expanded,
+/// and the lines inside `{ }` indented by 4 spaces per level
+fn normalize(sym: &Symbol) -> String {
+    let mut lines = Vec::new();
+    let mut depth: usize = 0;
+    let mut in_comment = false;
+
+    for line in sym.text.lines() {
+        let was_in_comment = in_comment;
+        let code = strip_comments(line, &mut in_comment);
+        let line = expand_tabs(line.trim_end());
+        let trimmed = line.trim_start();
+
+        let closes = code.trim_start().starts_with('}');
+        let indent = depth.saturating_sub(usize::from(closes));
+        if depth > 0 && !trimmed.is_empty() {
+            // the ` * ` lines of a block comment line up with its `/*`
+            let star = usize::from(was_in_comment && trimmed.starts_with('*'));
+            lines.push(format!("{}{}", " ".repeat(indent * 4 + star), trimmed));
+        } else {
+            lines.push(line.to_string());
+        }
+
+        for c in code.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Returns `line` with the tabs of its indentation expanded to 4 columns
+fn expand_tabs(line: &str) -> String {
+    let code = line.trim_start();
+    let mut indent = 0;
+    for c in line[..line.len() - code.len()].chars() {
+        indent = if c == '\t' {
+            indent / 4 * 4 + 4
+        } else {
+            indent + 1
+        };
+    }
+    format!("{}{}", " ".repeat(indent), code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::{TestDir, LABEL};
+    use crate::{tidy_block, Syncer};
+
+    #[test]
+    fn test_tidy() {
+        let dir = TestDir::new("tidy");
+        let from = dir.write(
+            "test1.h",
+            "/* header-sync start */\nvoid b_fn(void);\n#define B 2\n/* header-sync end */\n",
+        );
+        let to = dir.write(
+            "api.h",
+            "/* header-sync autogen start */\n// test1.h: header-sync\nvoid b_fn(void);\n\
+             #define B 2\nstruct s {\n\tint a;\n\t  int b;   \n};\nvoid a_fn(void);\n\
+             #define A 1\nvoid b_fn(void);\ntypedef int t_t;\nint var;\n\
+             /* header-sync autogen end */\n\
+             /* header-sync autogen:cond start */\n#ifdef A\nvoid b(void);\n#endif\nvoid a(void);\n\
+             /* header-sync autogen:cond end */\n",
+        );
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
+
+        let written = syncer.tidy().unwrap();
+        assert_eq!(written[0].regions, [""]);
+        let tidied = "#define A 1\n#define B 2\n\ntypedef int t_t;\n\nstruct s {\n    int a;\n\
+                      \x20   int b;\n};\n\nint var;\n\nvoid a_fn(void);\nvoid b_fn(void);\n";
+        let cond = "/* header-sync autogen:cond start */\n#ifdef A\nvoid b(void);\n#endif\n\
+                    void a(void);\n/* header-sync autogen:cond end */\n";
+        assert_eq!(
+            fs::read_to_string(&to).unwrap(),
+            format!(
+                "/* header-sync autogen start */\n{}/* header-sync autogen end */\n{}",
+                tidied, cond
+            )
+        );
+        assert!(syncer.tidy().unwrap().is_empty());
+        assert_eq!(
+            tidy_block(&[
+                "typedef int b_t;",
+                "typedef struct { b_t x; } a_t;",
+                "typedef struct { struct z z; } c_t;",
+                "struct z {int a;};",
+                "struct y {struct z z;};",
+                "/* lone */",
+                "",
+                "#define A 1",
+            ])
+            .unwrap(),
+            [
+                "/* lone */",
+                "",
+                "#define A 1",
+                "",
+                "typedef int b_t;",
+                "typedef struct { b_t x; } a_t;",
+                "",
+                "struct z {int a;};",
+                "typedef struct { struct z z; } c_t;",
+                "struct y {struct z z;};",
+            ]
+        );
+
+        syncer.set_tidy(true);
+        syncer.set_class_name("rom");
+        syncer.run().unwrap();
+        assert_eq!(
+            fs::read_to_string(&to).unwrap(),
+            format!(
+                "/* header-sync autogen start */\n// rom\n#define B 2\n\nvoid b_fn(void);\n\
+                 /* header-sync autogen end */\n{}",
+                cond
+            )
+        );
+    }
+
+    #[test]
+    fn test_tidy_sections() {
+        let dir = TestDir::new("tidy_sections");
+        let to = dir.write(
+            "api.h",
+            "/* header-sync autogen start */\n// a.h: header-sync\nvoid b_fn(void);\n#define B 2\n\
+             // b.h: header-sync\nvoid a_fn(void);\n#define A 1\n// note: keep\n\
+             /* header-sync autogen end */\n/* header-sync autogen:gpio start */\n\
+             // a.h: header-sync:gpio\nvoid gpio_b(void);\n// b.h: header-sync:gpio\n\
+             void gpio_a(void);\n/* header-sync autogen:gpio end */\n",
+        );
+        // without From files, the comments naming them are known by their form
+        let mut syncer = Syncer::new(vec![], vec![to.to_str().unwrap()], LABEL);
+        syncer.tidy().unwrap();
+        assert_eq!(
+            fs::read_to_string(&to).unwrap(),
+            "/* header-sync autogen start */\n// note: keep\n\n#define A 1\n#define B 2\n\n\
+             void a_fn(void);\nvoid b_fn(void);\n/* header-sync autogen end */\n\
+             /* header-sync autogen:gpio start */\nvoid gpio_a(void);\nvoid gpio_b(void);\n\
+             /* header-sync autogen:gpio end */\n"
+        );
+        assert!(syncer.warnings().is_empty());
+    }
+}
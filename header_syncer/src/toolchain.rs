@@ -0,0 +1,174 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use lang_c::driver::{Config, Flavor};
+
+/// The C preprocessor run for the `ast` parse mode, e.g. `arm-none-eabi-gcc -E`,
+/// and the directories it is searched in before `PATH`.
+///
+/// The `-D`, `-U` and `-I` options come from the [`Preprocessor`](crate::Preprocessor)
+/// of the sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toolchain {
+    command: String,
+    options: Vec<String>,
+    path: Vec<PathBuf>,
+}
+
+impl Toolchain {
+    /// The command is split at white space, e.g. `clang -E`, `-E` is added if it is missing;
+    /// `gcc -E` if it is empty. `-P` is dropped, as the declarations are found in the
+    /// header by the linemarkers it leaves out.
+    pub fn new(command: &str) -> Toolchain {
+        let mut words = command.split_whitespace().map(|w| w.to_string());
+        let command = words.next().unwrap_or("gcc".to_string());
+        let mut options: Vec<String> = words.filter(|w| w != "-P").collect();
+        if !options.iter().any(|o| o == "-E") {
+            options.insert(0, "-E".to_string());
+        }
+
+        Toolchain {
+            command,
+            options,
+            path: Vec::new(),
+        }
+    }
+
+    /// Adds directories to search the command in, separated like `PATH`,
+    /// i.e. by `:` on Unix and `;` on Windows
+    pub fn add_path(&mut self, paths: &str) {
+        self.path
+            .extend(env::split_paths(paths).filter(|p| !p.as_os_str().is_empty()));
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn options(&self) -> &[String] {
+        &self.options
+    }
+
+    /// Returns the command found in the toolchain directories or else in `PATH`,
+    /// a command with a directory is used as it is
+    pub fn resolve_command(&self) -> Result<PathBuf, String> {
+        let command = Path::new(&self.command);
+        if command.components().count() > 1 {
+            return Ok(command.to_path_buf());
+        }
+
+        let system_path = env::var_os("PATH").unwrap_or_default();
+        let dirs = self
+            .path
+            .iter()
+            .cloned()
+            .chain(env::split_paths(&system_path));
+        for dir in dirs {
+            for name in [
+                self.command.clone(),
+                format!("{}{}", self.command, env::consts::EXE_SUFFIX),
+            ] {
+                let candidate = dir.join(name);
+                if candidate.is_file() {
+                    return Ok(candidate);
+                }
+            }
+        }
+
+        Err(format!(
+            "{} is not found in the tool path or PATH",
+            self.command
+        ))
+    }
+
+    /// Returns the lang-c configuration running the preprocessor
+    pub fn config(&self) -> Result<Config, String> {
+        let command = self.resolve_command()?;
+        let is_clang = command
+            .file_stem()
+            .is_some_and(|stem| stem.to_string_lossy().contains("clang"));
+
+        Ok(Config {
+            cpp_command: command.to_string_lossy().to_string(),
+            cpp_options: self.options.clone(),
+            flavor: if is_clang {
+                Flavor::ClangC11
+            } else {
+                Flavor::GnuC11
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::test_util::{TestDir, EMPTY_BLOCK, LABEL};
+    use crate::{ConditionalMode, ParseMode, Preprocessor, SyncError, Syncer, Toolchain};
+
+    #[test]
+    fn test_toolchain() {
+        let gcc = Toolchain::new("");
+        assert_eq!(
+            (gcc.command(), gcc.options()),
+            ("gcc", &["-E".to_string()][..])
+        );
+        let clang = Toolchain::new("clang -P -std=c11");
+        assert_eq!(clang.options(), ["-E", "-std=c11"]);
+
+        let dir = TestDir::new("toolchain");
+        let bin = dir.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        let from = dir.join("uart.h");
+        let to = dir.join("api.h");
+        fs::write(
+            &from,
+            "/* header-sync start */\n#ifdef CONFIG_UART\nvoid uart_init(void);\n#endif\n\
+             void uart_deinit(void);\n/* header-sync end */\n",
+        )
+        .unwrap();
+        fs::write(&to, EMPTY_BLOCK).unwrap();
+        let mut syncer = Syncer::new(
+            vec![from.to_str().unwrap()],
+            vec![to.to_str().unwrap()],
+            LABEL,
+        );
+        syncer.set_parse_mode(ParseMode::Ast);
+
+        let mut pp = Preprocessor::new(ConditionalMode::Evaluate);
+        pp.define("CONFIG_UART");
+        syncer.set_preprocessor(pp);
+        let mut toolchain = Toolchain::new("test-cpp -E");
+        toolchain.add_path(&env::join_paths([&bin]).unwrap().to_string_lossy());
+        syncer.set_toolchain(toolchain.clone());
+        assert!(matches!(syncer.run(), Err(SyncError::Toolchain { .. })));
+
+        // without the linemarkers no declaration would be found
+        syncer.set_toolchain(Toolchain::new("gcc -P"));
+        syncer.run().unwrap();
+        let synced = "/* header-sync autogen start */\n// uart.h: header-sync\n\
+                      void uart_init(void);\nvoid uart_deinit(void);\n/* header-sync autogen end */\n";
+        assert_eq!(fs::read_to_string(&to).unwrap(), synced);
+        fs::write(&to, EMPTY_BLOCK).unwrap();
+        syncer.set_toolchain(toolchain.clone());
+
+        // a wrapper found in the tool path only, that fails without the `-D` of the sync
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let cpp = bin.join("test-cpp");
+            fs::write(
+                &cpp,
+                "#!/bin/sh\ncase \"$*\" in *-DCONFIG_UART*) ;; *) exit 1 ;; esac\nexec gcc \"$@\"\n",
+            )
+            .unwrap();
+            fs::set_permissions(&cpp, fs::Permissions::from_mode(0o755)).unwrap();
+            assert_eq!(toolchain.resolve_command().unwrap(), cpp);
+
+            syncer.run().unwrap();
+            assert_eq!(fs::read_to_string(&to).unwrap(), synced);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Watches files by polling their modification time and size
+pub struct FileWatcher {
+    files: Vec<(String, Option<(SystemTime, u64)>)>,
+}
+
+impl FileWatcher {
+    pub fn new(files: &[String]) -> FileWatcher {
+        let mut watched: Vec<String> = files.to_vec();
+        watched.sort();
+        watched.dedup();
+
+        FileWatcher {
+            files: watched.into_iter().map(|f| (f.clone(), stat(&f))).collect(),
+        }
+    }
+
+    /// Returns the files changed, created or removed since the last call
+    pub fn changed(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+
+        for (f, last) in &mut self.files {
+            let now = stat(f);
+            if now != *last {
+                *last = now;
+                changed.push(f.clone());
+            }
+        }
+
+        changed
+    }
+
+    /// Blocks until some files changed and then stayed the same for `debounce`,
+    /// returns the changed files
+    pub fn wait(&mut self, poll: Duration, debounce: Duration) -> Vec<String> {
+        let mut changed: Vec<String> = Vec::new();
+        let mut last_change = Instant::now();
+
+        loop {
+            thread::sleep(poll);
+
+            let now_changed = self.changed();
+            if !now_changed.is_empty() {
+                for f in now_changed {
+                    if !changed.contains(&f) {
+                        changed.push(f);
+                    }
+                }
+                last_change = Instant::now();
+            } else if !changed.is_empty() && last_change.elapsed() >= debounce {
+                return changed;
+            }
+        }
+    }
+}
+
+fn stat(f: &str) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(f).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::test_util::{SyncTest, TestDir};
+    use crate::FileWatcher;
+
+    #[test]
+    fn test_watch() {
+        let test = SyncTest::new("watch", "void v1(void);\n");
+        let mut syncer = test.syncer();
+        let mut watcher = FileWatcher::new(&syncer.sources());
+        let written = syncer.run().unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].line_counts(), (2, 0));
+        assert!(watcher.changed().is_empty());
+
+        test.set_code("void v2(int a);\n");
+        assert_eq!(watcher.changed(), [test.from.as_str()]);
+        assert!(watcher.changed().is_empty());
+        assert_eq!(syncer.run().unwrap()[0].line_counts(), (1, 1));
+        assert!(syncer.run().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wait_debounce() {
+        let dir = TestDir::new("debounce");
+        let file = dir.write("a.h", "");
+        let other = dir.write("b.h", "");
+        let files = [&file, &other].map(|f| f.to_string_lossy().to_string());
+        let mut watcher = FileWatcher::new(&files);
+
+        // the file keeps changing for a while, e.g. an editor writing it in parts
+        let writer = thread::spawn(move || {
+            for i in 1..=3 {
+                thread::sleep(Duration::from_millis(30));
+                fs::write(&file, "x".repeat(i)).unwrap();
+            }
+            Instant::now()
+        });
+        let debounce = Duration::from_millis(150);
+        let changed = watcher.wait(Duration::from_millis(5), debounce);
+        let returned = Instant::now();
+
+        assert_eq!(changed, [files[0].as_str()]);
+        assert!(returned - writer.join().unwrap() >= debounce);
+        assert!(watcher.changed().is_empty());
+    }
+}